@@ -0,0 +1,313 @@
+use anyhow::{anyhow, bail, Result};
+use lazy_static::lazy_static;
+use regex::Regex;
+
+use crate::{parse_ms, Format, SubData, Subtitle, TimeSpan};
+
+/// The `[Events]` columns used when a file doesn't specify them.
+static DEFAULT_EVENT_FORMAT: &str =
+    "Layer, Start, End, Style, Name, MarginL, MarginR, MarginV, Effect, Text";
+
+/// Parse the text of an Advanced SubStation Alpha (.ass) or SubStation Alpha (.ssa) file.
+/// Only `Dialogue:` lines become subtitles. Everything else, including `Comment:` lines, is
+/// kept verbatim.
+pub(crate) fn parse(text: &str) -> Result<SubData> {
+    let line_ending = if text.split('\n').next().unwrap_or("").ends_with('\r') {
+        "\r\n"
+    } else {
+        "\n"
+    };
+    let with_ending = |line: &str| {
+        if line.ends_with('\n') {
+            line.to_string()
+        } else {
+            format!("{}{}", line, line_ending)
+        }
+    };
+
+    let mut header = vec![];
+    let mut subs = Vec::new();
+    let mut preceding = Vec::new();
+    let mut event_format = None;
+    let mut section = String::new();
+    for line in text.split_inclusive('\n') {
+        let trimmed = line.trim();
+        if trimmed.starts_with('[') && trimmed.ends_with(']') {
+            section = trimmed.to_ascii_lowercase();
+        }
+
+        let content = line.trim_start().trim_end_matches(&['\r', '\n'][..]);
+        if let Some(dialogue) = content.strip_prefix("Dialogue:") {
+            let format = event_format.get_or_insert_with(|| columns(DEFAULT_EVENT_FORMAT));
+            let mut fields: Vec<String> = dialogue
+                .trim_start()
+                .splitn(format.len(), ',')
+                .map(String::from)
+                .collect();
+            if fields.len() < format.len() {
+                bail!("Dialogue line has too few fields: {:#?}", line);
+            }
+            let text = fields.pop().unwrap();
+            let field = |name| {
+                let i = format.iter().position(|column| column == name);
+                i.map(|i| fields[i].as_str())
+                    .ok_or_else(|| anyhow!("The event format has no {:?} field", name))
+            };
+            let time_span = TimeSpan::new(parse_ms(field("start")?)?, parse_ms(field("end")?)?);
+
+            subs.push(Subtitle {
+                number: subs.len() as i64 + 1,
+                time_span,
+                lines: text
+                    .split("\\N")
+                    .map(|line| format!("{}{}", line, line_ending))
+                    .collect(),
+                ass_fields: fields,
+                preceding: std::mem::take(&mut preceding),
+                ..Default::default()
+            });
+        } else if subs.is_empty() && event_format.is_none() {
+            if section == "[events]" {
+                if let Some(format) = trimmed.strip_prefix("Format:") {
+                    event_format = Some(columns(format));
+                }
+            }
+            header.push(with_ending(line));
+        } else {
+            preceding.push(with_ending(line));
+        }
+    }
+
+    Ok(SubData {
+        subs,
+        line_ending: line_ending.to_string(),
+        format: Format::Ass,
+        header,
+        trailer: preceding,
+        ..Default::default()
+    })
+}
+
+/// Split a `Format:` line into lowercase column names.
+fn columns(format: &str) -> Vec<String> {
+    format
+        .split(',')
+        .map(|column| column.trim().to_ascii_lowercase())
+        .collect()
+}
+
+/// Find the `Format:` line of the given section.
+fn section_format(header: &[String], section_name: &str) -> Option<Vec<String>> {
+    let mut section = "";
+    for line in header {
+        let trimmed = line.trim();
+        if trimmed.starts_with('[') && trimmed.ends_with(']') {
+            section = trimmed;
+        } else if section.eq_ignore_ascii_case(section_name) {
+            if let Some(format) = trimmed.strip_prefix("Format:") {
+                return Some(columns(format));
+            }
+        }
+    }
+    None
+}
+
+pub(crate) fn write(data: &SubData, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    for line in data.header.iter() {
+        f.write_str(line)?;
+    }
+
+    let format =
+        section_format(&data.header, "[Events]").unwrap_or_else(|| columns(DEFAULT_EVENT_FORMAT));
+    for sub in data.subs.iter() {
+        for line in sub.preceding.iter() {
+            f.write_str(line)?;
+        }
+        f.write_str("Dialogue: ")?;
+        for (i, column) in format[..format.len() - 1].iter().enumerate() {
+            match column.as_str() {
+                "start" => f.write_str(&ass_timestamp(sub.time_span.start_ms))?,
+                "end" => f.write_str(&ass_timestamp(sub.time_span.end_ms))?,
+                _ => f.write_str(sub.ass_fields.get(i).map_or("", String::as_str))?,
+            }
+            f.write_str(",")?;
+        }
+        let text: Vec<&str> = sub
+            .lines
+            .iter()
+            .map(|line| line.trim_end_matches(&['\r', '\n'][..]))
+            .collect();
+        write!(f, "{}{}", text.join("\\N"), data.line_ending)?;
+    }
+
+    for line in data.trailer.iter() {
+        f.write_str(line)?;
+    }
+    Ok(())
+}
+
+/// Format milliseconds as [-]h:mm:ss.cc. ASS times are in hundredths of a second.
+fn ass_timestamp(ms: i64) -> String {
+    let sign = if ms < 0 { "-" } else { "" };
+    let centiseconds = (ms.abs() + 5) / 10;
+    format!(
+        "{}{}:{:02}:{:02}.{:02}",
+        sign,
+        centiseconds / 360_000,
+        centiseconds / 6_000 % 60,
+        centiseconds / 100 % 60,
+        centiseconds % 100
+    )
+}
+
+/// Does this subtitle's style place it at the bottom of the screen? Styles that can't be found
+/// are assumed to use the default alignment, which is bottom center.
+pub fn style_is_bottom(header: &[String], sub: &Subtitle) -> bool {
+    let event_format =
+        section_format(header, "[Events]").unwrap_or_else(|| columns(DEFAULT_EVENT_FORMAT));
+    let style_name = event_format
+        .iter()
+        .position(|column| column == "style")
+        .and_then(|i| sub.ass_fields.get(i))
+        .map_or("", |style| style.trim());
+
+    // SSA files have "[V4 Styles]", ASS files have "[V4+ Styles]". Both use 1-3 for bottom alignment.
+    let style_format = section_format(header, "[V4+ Styles]")
+        .or_else(|| section_format(header, "[V4 Styles]"))
+        .unwrap_or_default();
+    let alignment_column = match style_format.iter().position(|column| column == "alignment") {
+        Some(i) => i,
+        None => return true,
+    };
+    for line in header {
+        if let Some(style) = line.trim().strip_prefix("Style:") {
+            let fields: Vec<&str> = style.split(',').map(str::trim).collect();
+            if fields.first() == Some(&style_name) {
+                return fields
+                    .get(alignment_column)
+                    .and_then(|alignment| alignment.parse::<u8>().ok())
+                    .is_none_or(|alignment| (1..=3).contains(&alignment));
+            }
+        }
+    }
+    true
+}
+
+/// Is this a SubStation Alpha script (`ScriptType: v4.00`), rather than an Advanced
+/// SubStation Alpha one? They position subtitles with different override tags.
+pub fn is_ssa(header: &[String]) -> bool {
+    header.iter().any(|line| {
+        line.trim()
+            .strip_prefix("ScriptType:")
+            .is_some_and(|script_type| script_type.trim().eq_ignore_ascii_case("v4.00"))
+    })
+}
+
+/// Replace any `\anN` or `\aN` alignment override at the beginning of the line with the given
+/// one, or with `None`, just remove it. Other override tags in the same block are kept. The
+/// alignment is a numpad position like 8 for top center, which SSA scripts (`ssa`) write with
+/// the older `\aN` tag, where top center is 6.
+pub fn set_alignment(line: &str, alignment: Option<u8>, ssa: bool) -> String {
+    lazy_static! {
+        static ref BLOCK: Regex = Regex::new(r"^\{[^}]*\}").unwrap();
+        static ref AN: Regex = Regex::new(r"\\an?\d+").unwrap();
+    }
+    let mut rest = line;
+    let mut tags = String::new();
+    if let Some(block) = BLOCK.find(line) {
+        tags = AN.replace_all(block.as_str(), "").to_string();
+        if tags == "{}" {
+            tags.clear();
+        }
+        rest = &line[block.end()..];
+    }
+    match alignment {
+        Some(alignment) if ssa => {
+            // The rows are numbered bottom 1-3, top 5-7 and middle 9-11:
+            let legacy = (alignment - 1) % 3 + 1 + [0, 8, 4][(alignment as usize - 1) / 3];
+            format!("{{\\a{}}}{}{}", legacy, tags, rest)
+        }
+        Some(alignment) => format!("{{\\an{}}}{}{}", alignment, tags, rest),
+        None => format!("{}{}", tags, rest),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Format, SubData, TimeSpan};
+
+    use super::{ass_timestamp, is_ssa, set_alignment, style_is_bottom};
+
+    static SAMPLE: &str = "[Script Info]\r\n\
+        ; a comment\r\n\
+        ScriptType: v4.00+\r\n\
+        \r\n\
+        [V4+ Styles]\r\n\
+        Format: Name, Fontname, Fontsize, Alignment\r\n\
+        Style: Default,Arial,20,2\r\n\
+        Style: Sign,Arial,20,8\r\n\
+        \r\n\
+        [Events]\r\n\
+        Format: Layer, Start, End, Style, Name, MarginL, MarginR, MarginV, Effect, Text\r\n\
+        Dialogue: 0,0:00:01.00,0:00:02.50,Default,,0,0,0,,{\\i1}Hello,\\Nworld{\\i0}\r\n\
+        Comment: 0,0:00:02.00,0:00:03.00,Default,,0,0,0,,not shown\r\n\
+        Dialogue: 1,0:01:03.20,1:00:00.00,Sign,Bob,0,0,0,,{\\an8\\b1}Sign\r\n";
+
+    #[test]
+    fn test_parse_ass() {
+        let data = SubData::parse(SAMPLE, Format::Ass, None).unwrap();
+        assert_eq!(data.header.len(), 11);
+        assert_eq!(data.line_ending, "\r\n");
+        assert_eq!(data.subs.len(), 2);
+        assert_eq!(data.subs[0].time_span, TimeSpan::new(1000, 2500));
+        assert_eq!(
+            data.subs[0].lines,
+            vec!["{\\i1}Hello,\r\n", "world{\\i0}\r\n"]
+        );
+        assert_eq!(data.subs[1].time_span, TimeSpan::new(63200, 3_600_000));
+        assert_eq!(data.subs[1].ass_fields[3], "Sign");
+        assert_eq!(
+            data.subs[1].preceding,
+            vec!["Comment: 0,0:00:02.00,0:00:03.00,Default,,0,0,0,,not shown\r\n"]
+        );
+
+        assert!(!style_is_bottom(&data.header, &data.subs[1]));
+        assert!(style_is_bottom(&data.header, &data.subs[0]));
+    }
+
+    #[test]
+    fn test_ass_round_trip() {
+        let data = SubData::parse(SAMPLE, Format::Ass, None).unwrap();
+        assert_eq!(format!("{}", data), SAMPLE);
+    }
+
+    #[test]
+    fn test_ass_timestamp() {
+        assert_eq!(ass_timestamp(0), "0:00:00.00");
+        assert_eq!(ass_timestamp(3_723_456), "1:02:03.46");
+        assert_eq!(ass_timestamp(59_996), "0:01:00.00");
+        assert_eq!(ass_timestamp(-1500), "-0:00:01.50");
+    }
+
+    #[test]
+    fn test_set_alignment() {
+        assert_eq!(set_alignment("Hi", Some(8), false), "{\\an8}Hi");
+        assert_eq!(set_alignment("{\\an2}Hi", Some(8), false), "{\\an8}Hi");
+        assert_eq!(
+            set_alignment("{\\an8\\i1}Hi", Some(2), false),
+            "{\\an2}{\\i1}Hi"
+        );
+        assert_eq!(set_alignment("{\\an8}Hi", None, false), "Hi");
+        assert_eq!(set_alignment("{\\i1\\an8}Hi", None, false), "{\\i1}Hi");
+
+        // SSA scripts use the older tags:
+        assert_eq!(set_alignment("{\\a2}Hi", Some(8), true), "{\\a6}Hi");
+        assert_eq!(
+            set_alignment("{\\a6\\i1}Hi", Some(2), true),
+            "{\\a2}{\\i1}Hi"
+        );
+        assert_eq!(set_alignment("{\\a10}Hi", None, true), "Hi");
+        assert!(is_ssa(&["ScriptType: v4.00\n".to_string()]));
+        assert!(!is_ssa(&["ScriptType: v4.00+\n".to_string()]));
+    }
+}
@@ -0,0 +1,344 @@
+//! Automatic syncing against the audio of the video. The audio is split into speech and
+//! silence by its loudness, and the subtitles are moved to where they cover the most speech.
+
+use std::io::{ErrorKind, Read};
+
+use anyhow::{bail, Result};
+use log::info;
+
+use crate::speed;
+use crate::sync::Anchor;
+use crate::Subtitle;
+
+/// The audio is judged in blocks of this many milliseconds.
+const FRAME_MS: i64 = 10;
+
+/// Offsets are first tried in steps of this many frames, then refined around the best ones.
+const COARSE_STEP: i64 = 50;
+
+/// How many of the best coarse offsets are refined for each scale.
+const CANDIDATES: usize = 5;
+
+/// A frame is speech if it's this many decibels louder than the background noise.
+const SPEECH_DB: f64 = 10.0;
+
+/// Pauses shorter than this are part of the speech around them.
+const MAX_PAUSE_MS: i64 = 300;
+
+/// Sounds shorter than this aren't speech.
+const MIN_SPEECH_MS: i64 = 100;
+
+/// Adds up the loudness of each frame of mono samples as they are read.
+struct Meter {
+    frame_len: usize,
+    power: f64,
+    count: usize,
+    loudness: Vec<f64>,
+}
+
+impl Meter {
+    fn new(sample_rate: u32) -> Self {
+        Meter {
+            frame_len: (sample_rate as i64 * FRAME_MS / 1000).max(1) as usize,
+            power: 0.0,
+            count: 0,
+            loudness: vec![],
+        }
+    }
+
+    fn push(&mut self, sample: f32) {
+        self.power += (sample as f64).powi(2);
+        self.count += 1;
+        if self.count == self.frame_len {
+            self.end_frame();
+        }
+    }
+
+    fn end_frame(&mut self) {
+        let power = self.power / self.count as f64;
+        self.loudness.push(10.0 * (power + 1e-10).log10());
+        self.power = 0.0;
+        self.count = 0;
+    }
+
+    /// The loudness of each frame in decibels, including a last frame that isn't full.
+    fn finish(mut self) -> Vec<f64> {
+        if self.count > 0 {
+            self.end_frame();
+        }
+        self.loudness
+    }
+}
+
+/// Read PCM audio from a WAV file, mixing all channels into one, and measure the loudness of
+/// each frame in decibels. The samples aren't kept, so long films don't need much memory.
+pub fn read_loudness(reader: impl Read) -> Result<Vec<f64>> {
+    let mut reader = hound::WavReader::new(reader)?;
+    let spec = reader.spec();
+    let channels = spec.channels as usize;
+    let mut meter = Meter::new(spec.sample_rate);
+    let (mut sum, mut channel) = (0.0, 0);
+    let mut push = |sample: f32| {
+        sum += sample;
+        channel += 1;
+        if channel == channels {
+            meter.push(sum / channels as f32);
+            sum = 0.0;
+            channel = 0;
+        }
+    };
+    match spec.sample_format {
+        hound::SampleFormat::Float => {
+            for sample in reader.samples::<f32>() {
+                push(sample?);
+            }
+        }
+        hound::SampleFormat::Int => {
+            let max = (1_i64 << (spec.bits_per_sample - 1)) as f32;
+            for sample in reader.samples::<i32>() {
+                push(sample? as f32 / max);
+            }
+        }
+    }
+    Ok(meter.finish())
+}
+
+/// Measure the loudness of raw mono audio in 32 bit little endian floats, like ffmpeg writes
+/// with `-f f32le`.
+pub fn read_raw_loudness(mut reader: impl Read, sample_rate: u32) -> Result<Vec<f64>> {
+    let mut meter = Meter::new(sample_rate);
+    let mut sample = [0; 4];
+    loop {
+        match reader.read_exact(&mut sample) {
+            Ok(()) => meter.push(f32::from_le_bytes(sample)),
+            Err(err) if err.kind() == ErrorKind::UnexpectedEof => break,
+            Err(err) => return Err(err.into()),
+        }
+    }
+    Ok(meter.finish())
+}
+
+/// Decide which frames of the audio contain speech, from their loudness. The background noise
+/// level is taken from the quieter frames, so the recording level doesn't matter.
+pub fn detect_speech(loudness: &[f64]) -> Vec<bool> {
+    if loudness.is_empty() {
+        return vec![];
+    }
+    let mut sorted = loudness.to_vec();
+    sorted.sort_by(f64::total_cmp);
+    let noise = sorted[sorted.len() / 10];
+
+    let mut speech: Vec<bool> = loudness.iter().map(|&db| db > noise + SPEECH_DB).collect();
+    fill_runs(&mut speech, false, MAX_PAUSE_MS / FRAME_MS);
+    fill_runs(&mut speech, true, MIN_SPEECH_MS / FRAME_MS);
+    speech
+}
+
+/// Flip runs of `value` that are shorter than `min_len` frames and are between two runs of
+/// the other value.
+fn fill_runs(frames: &mut [bool], value: bool, min_len: i64) {
+    let mut start = 0;
+    while start < frames.len() {
+        let len = frames[start..]
+            .iter()
+            .take_while(|&&f| f == frames[start])
+            .count();
+        if frames[start] == value
+            && start > 0
+            && start + len < frames.len()
+            && (len as i64) < min_len
+        {
+            frames[start..start + len]
+                .iter_mut()
+                .for_each(|f| *f = !value);
+        }
+        start += len;
+    }
+}
+
+/// Work out the `--sync` anchors that move the subtitles onto the speech in the audio.
+pub fn find_anchors(subs: &[Subtitle], speech: &[bool]) -> Result<Vec<Anchor>> {
+    if subs.len() < 2 {
+        bail!("At least two subtitles are needed to sync to the audio.");
+    }
+    if !speech.contains(&true) {
+        bail!("No speech was found in the audio.");
+    }
+
+    // Speech counts for the subtitles and silence against them. With prefix sums, the score
+    // of a subtitle at any position is a subtraction.
+    let mut prefix = vec![0_i64];
+    for &frame in speech {
+        prefix.push(prefix.last().unwrap() + if frame { 1 } else { -1 });
+    }
+    let audio_len = speech.len() as i64;
+    let score = |spans: &[(i64, i64)], offset: i64| -> i64 {
+        spans
+            .iter()
+            .map(|&(start, end)| {
+                let (start, end) = (start + offset, end + offset);
+                // Anything outside the audio counts as silence:
+                let outside = (0.min(end) - start).max(0) + (end - audio_len.max(start)).max(0);
+                let (start, end) = (start.clamp(0, audio_len), end.clamp(0, audio_len));
+                prefix[end as usize] - prefix[start as usize] - outside
+            })
+            .sum()
+    };
+
+    let mut best = (i64::MIN, 1.0, 0);
+    for scale in speed::scales() {
+        let spans: Vec<(i64, i64)> = subs
+            .iter()
+            .map(|sub| {
+                let frame = |ms: i64| (scale * ms as f64 / FRAME_MS as f64).round() as i64;
+                (frame(sub.time_span.start_ms), frame(sub.time_span.end_ms))
+            })
+            .collect();
+        let first = spans.iter().map(|span| span.0).min().unwrap();
+        let last = spans.iter().map(|span| span.1).max().unwrap();
+
+        let mut coarse: Vec<(i64, i64)> = (-last..=audio_len - first)
+            .step_by(COARSE_STEP as usize)
+            .map(|offset| (score(&spans, offset), offset))
+            .collect();
+        coarse.sort_by(|a, b| b.cmp(a));
+        for &(_, around) in coarse.iter().take(CANDIDATES) {
+            for offset in around - COARSE_STEP..=around + COARSE_STEP {
+                let score = score(&spans, offset);
+                if score > best.0 {
+                    best = (score, scale, offset);
+                }
+            }
+        }
+    }
+
+    let (score, scale, offset) = best;
+    if score <= 0 {
+        bail!("The subtitles don't line up with the speech in the audio at any offset.");
+    }
+    info!(
+        "Best match with the audio: a scale of {} and an offset of {} ms.",
+        scale,
+        offset * FRAME_MS
+    );
+    let first = subs.iter().map(|sub| sub.time_span.start_ms).min().unwrap();
+    let last = subs.iter().map(|sub| sub.time_span.start_ms).max().unwrap();
+    if first == last {
+        bail!("The subtitles all start at the same time, so they can't be synced.");
+    }
+    let map = |ms: i64| (scale * ms as f64).round() as i64 + offset * FRAME_MS;
+    Ok(vec![
+        Anchor::new(first, map(first)),
+        Anchor::new(last, map(last)),
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use crate::sync::map_time;
+    use crate::test_subs::dialogue;
+
+    use super::{detect_speech, find_anchors, read_loudness, read_raw_loudness};
+
+    const RATE: u32 = 8000;
+
+    /// Quiet noise, with loud noise where someone speaks.
+    fn audio(speech: &[(i64, i64)], length_ms: i64) -> Vec<f32> {
+        let mut seed: u32 = 12345;
+        (0..length_ms * RATE as i64 / 1000)
+            .map(|i| {
+                seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                let noise = (seed >> 16) as f32 / 32768.0 - 1.0;
+                let ms = i * 1000 / RATE as i64;
+                let loud = speech
+                    .iter()
+                    .any(|&(start, end)| (start..end).contains(&ms));
+                noise * if loud { 0.3 } else { 0.005 }
+            })
+            .collect()
+    }
+
+    /// The loudness of mono samples, read back from a WAV file.
+    fn loudness(samples: &[f32]) -> Vec<f64> {
+        let spec = hound::WavSpec {
+            channels: 1,
+            sample_rate: RATE,
+            bits_per_sample: 32,
+            sample_format: hound::SampleFormat::Float,
+        };
+        let mut file = Cursor::new(vec![]);
+        let mut writer = hound::WavWriter::new(&mut file, spec).unwrap();
+        for &sample in samples {
+            writer.write_sample(sample).unwrap();
+        }
+        writer.finalize().unwrap();
+        file.set_position(0);
+        read_loudness(file).unwrap()
+    }
+
+    #[test]
+    fn test_detect_speech() {
+        let samples = audio(&[(1000, 2000), (2200, 3000)], 5000);
+        let speech = detect_speech(&loudness(&samples));
+        assert_eq!(speech.len(), 500);
+        assert!(!speech[50]);
+        // The short pause is bridged:
+        assert!(speech[100..300].iter().all(|&frame| frame));
+        assert!(!speech[350]);
+
+        let raw: Vec<u8> = samples
+            .iter()
+            .flat_map(|sample| sample.to_le_bytes())
+            .collect();
+        assert_eq!(
+            read_raw_loudness(&raw[..], RATE).unwrap(),
+            loudness(&samples)
+        );
+    }
+
+    #[test]
+    fn test_find_anchors() {
+        let subs = dialogue(40, 3_000, 300);
+        let length = subs.last().unwrap().time_span.end_ms + 10_000;
+
+        // The speech happens 4.2 seconds later than the subtitles, and the video is slower:
+        let scale = 25.0 / 24.0;
+        let moved = |ms: i64| (ms as f64 * scale) as i64 + 4200;
+        let speech: Vec<(i64, i64)> = subs
+            .iter()
+            .map(|sub| (moved(sub.time_span.start_ms), moved(sub.time_span.end_ms)))
+            .collect();
+        let samples = audio(&speech, length);
+        let anchors = find_anchors(&subs, &detect_speech(&loudness(&samples))).unwrap();
+        for sub in &subs {
+            let synced = map_time(&anchors, sub.time_span.start_ms);
+            assert!((synced - moved(sub.time_span.start_ms)).abs() <= 30);
+        }
+
+        assert!(find_anchors(&subs, &detect_speech(&loudness(&audio(&[], length)))).is_err());
+    }
+
+    #[test]
+    fn test_read_loudness() {
+        let spec = hound::WavSpec {
+            channels: 2,
+            sample_rate: RATE,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let mut file = Cursor::new(vec![]);
+        let mut writer = hound::WavWriter::new(&mut file, spec).unwrap();
+        for sample in [16384_i16, 0, -32768, -32768] {
+            writer.write_sample(sample).unwrap();
+        }
+        writer.finalize().unwrap();
+
+        file.set_position(0);
+        // The channels are mixed into 0.25 and -1.0, in a frame that isn't full:
+        let loudness = read_loudness(file).unwrap();
+        assert_eq!(loudness.len(), 1);
+        assert!((loudness[0] - 10.0 * (0.53125_f64).log10()).abs() < 1e-6);
+    }
+}
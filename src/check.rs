@@ -0,0 +1,390 @@
+//! Finding problems in subtitle files without changing them, for `--check`.
+
+use std::collections::HashSet;
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+
+use lazy_static::lazy_static;
+use regex::Regex;
+use serde::{Serialize, Serializer};
+
+use crate::wrap;
+use crate::{Format, Milliseconds, SubData, Subtitle};
+
+/// Lines longer than this are hard to read in time. This is what most streaming services
+/// allow. Chinese, Japanese and Korean characters count as two, as for `--rewrap`.
+pub const MAX_LINE_LENGTH: usize = 42;
+
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    /// Players cope, but the subtitles may be hard to read or not what was meant.
+    Warning,
+    /// Players may show the subtitles at the wrong time, or not at all.
+    Error,
+}
+
+/// A problem with a subtitle file. It's located by the line and column in the file when
+/// that's known, otherwise by the subtitle number.
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub struct Finding {
+    pub severity: Severity,
+    /// A short name for the kind of problem, like `overlap`, for scripts to filter on.
+    pub kind: &'static str,
+    pub line: Option<usize>,
+    pub column: Option<usize>,
+    pub subtitle: Option<i64>,
+    pub message: String,
+}
+
+impl Finding {
+    fn new(severity: Severity, kind: &'static str, message: String) -> Self {
+        Self {
+            severity,
+            kind,
+            line: None,
+            column: None,
+            subtitle: None,
+            message,
+        }
+    }
+
+    /// A file that couldn't be read or parsed at all.
+    pub fn unreadable(err: &anyhow::Error) -> Self {
+        Self::new(Severity::Error, "unreadable", format!("{:#}", err))
+    }
+
+    fn at_subtitle(severity: Severity, kind: &'static str, number: i64, message: String) -> Self {
+        Self {
+            subtitle: Some(number),
+            ..Self::new(severity, kind, message)
+        }
+    }
+}
+
+/// The findings for one file.
+#[derive(Debug, Serialize)]
+pub struct Report {
+    #[serde(serialize_with = "lossy_path")]
+    pub file: PathBuf,
+    pub findings: Vec<Finding>,
+}
+
+/// Check parsed subtitles. The text they were parsed from is needed for the line endings.
+pub fn check(text: &str, data: &SubData) -> Vec<Finding> {
+    use Severity::*;
+
+    let mut findings: Vec<Finding> = data
+        .problems
+        .iter()
+        .map(|problem| Finding {
+            line: Some(problem.line),
+            column: Some(problem.column),
+            ..Finding::new(Warning, "syntax", problem.message.clone())
+        })
+        .collect();
+
+    let crlf = text.matches("\r\n").count();
+    let lf = text.matches('\n').count() - crlf;
+    if crlf > 0 && lf > 0 {
+        findings.push(Finding::new(
+            Warning,
+            "mixed-line-endings",
+            format!(
+                "Mixed line endings: {} lines end with CRLF and {} with LF",
+                crlf, lf
+            ),
+        ));
+    }
+
+    let mut seen_numbers = HashSet::new();
+    let mut previous: Option<&Subtitle> = None;
+    for sub in &data.subs {
+        let number = sub.number;
+        let span = &sub.time_span;
+        // Numbers in other formats are only counted when reading:
+        if data.format == Format::Srt {
+            if !seen_numbers.insert(number) {
+                findings.push(Finding::at_subtitle(
+                    Warning,
+                    "duplicate-number",
+                    number,
+                    format!("Subtitle number {} was already used", number),
+                ));
+            } else if previous.is_some_and(|previous| number < previous.number) {
+                findings.push(Finding::at_subtitle(
+                    Warning,
+                    "number-order",
+                    number,
+                    format!(
+                        "Subtitle {} comes after subtitle {}",
+                        number,
+                        previous.unwrap().number
+                    ),
+                ));
+            }
+        }
+
+        if span.start_ms < 0 || span.end_ms < 0 {
+            findings.push(Finding::at_subtitle(
+                Error,
+                "negative-time",
+                number,
+                format!("Negative time: {}", span),
+            ));
+        }
+        if span.end_ms <= span.start_ms {
+            findings.push(Finding::at_subtitle(
+                Error,
+                "duration",
+                number,
+                if span.end_ms == span.start_ms {
+                    format!("The subtitle is never shown: {}", span)
+                } else {
+                    format!("The subtitle ends before it starts: {}", span)
+                },
+            ));
+        }
+        if let Some(previous) = previous {
+            if span.start_ms < previous.time_span.start_ms {
+                findings.push(Finding::at_subtitle(
+                    Warning,
+                    "start-order",
+                    number,
+                    format!(
+                        "Starts at {}, before subtitle {} does",
+                        Milliseconds(span.start_ms),
+                        previous.number
+                    ),
+                ));
+            }
+        }
+
+        if sub.lines.iter().all(|line| plain_text(line).is_empty()) {
+            findings.push(Finding::at_subtitle(
+                Warning,
+                "empty",
+                number,
+                "The subtitle has no text".to_string(),
+            ));
+        }
+        for line in &sub.lines {
+            let length = wrap::width(&plain_text(line));
+            if length > MAX_LINE_LENGTH {
+                findings.push(Finding::at_subtitle(
+                    Warning,
+                    "line-length",
+                    number,
+                    format!(
+                        "A line is {} characters long, more than {}",
+                        length, MAX_LINE_LENGTH
+                    ),
+                ));
+            }
+        }
+        previous = Some(sub);
+    }
+
+    // Overlaps are found in time order, whatever the file order. Each subtitle is compared
+    // with the one that ends last of those before it:
+    let mut by_start: Vec<&Subtitle> = data.subs.iter().collect();
+    by_start.sort_by_key(|sub| sub.time_span.start_ms);
+    let mut latest: Option<&Subtitle> = None;
+    for sub in by_start {
+        if let Some(latest) = latest {
+            let overlap = latest.time_span.end_ms - sub.time_span.start_ms;
+            if overlap > 0 {
+                findings.push(Finding::at_subtitle(
+                    Warning,
+                    "overlap",
+                    latest.number,
+                    format!(
+                        "Overlaps subtitle {} by {:.3} seconds",
+                        sub.number,
+                        overlap as f64 / 1000.0
+                    ),
+                ));
+            }
+        }
+        if latest.is_none_or(|latest| sub.time_span.end_ms > latest.time_span.end_ms) {
+            latest = Some(sub);
+        }
+    }
+    findings
+}
+
+/// A line without formatting tags like `<i>` or `{\an8}`, and without the line ending.
+pub(crate) fn plain_text(line: &str) -> String {
+    lazy_static! {
+        static ref TAG: Regex = Regex::new(r"<[^>]*>|\{[^}]*\}").unwrap();
+    }
+    TAG.replace_all(line, "").trim().to_string()
+}
+
+/// The exit code for `--check`: 0 if nothing was found, 2 for warnings and 3 for errors.
+/// 1 is left for failing to run at all.
+pub fn exit_code(reports: &[Report]) -> i32 {
+    let worst = reports
+        .iter()
+        .flat_map(|report| &report.findings)
+        .map(|finding| finding.severity)
+        .max();
+    match worst {
+        None => 0,
+        Some(Severity::Warning) => 2,
+        Some(Severity::Error) => 3,
+    }
+}
+
+/// The findings, one per line, followed by a summary.
+pub fn to_text(reports: &[Report]) -> String {
+    let mut text = String::new();
+    let (mut errors, mut warnings) = (0, 0);
+    for report in reports {
+        for finding in &report.findings {
+            let severity = match finding.severity {
+                Severity::Error => {
+                    errors += 1;
+                    "error"
+                }
+                Severity::Warning => {
+                    warnings += 1;
+                    "warning"
+                }
+            };
+            let location = match (finding.line, finding.column, finding.subtitle) {
+                (Some(line), Some(column), _) => format!("line {}, column {}: ", line, column),
+                (_, _, Some(number)) => format!("subtitle {}: ", number),
+                _ => String::new(),
+            };
+            writeln!(
+                text,
+                "{}: {}: {}{}",
+                report.file.display(),
+                severity,
+                location,
+                finding.message
+            )
+            .unwrap();
+        }
+    }
+    let files = count(reports.len(), "file");
+    if errors + warnings == 0 {
+        writeln!(text, "No problems found in {}.", files).unwrap();
+    } else {
+        writeln!(
+            text,
+            "Found {} and {} in {}.",
+            count(errors, "error"),
+            count(warnings, "warning"),
+            files
+        )
+        .unwrap();
+    }
+    text
+}
+
+/// A number of things, like "1 file" or "2 files".
+pub(crate) fn count(n: usize, thing: &str) -> String {
+    format!("{} {}{}", n, thing, if n == 1 { "" } else { "s" })
+}
+
+/// The reports as a JSON array, with a `file` and its `findings` for each file.
+pub fn to_json(reports: &[Report]) -> String {
+    // Everything is valid JSON, as paths are written lossily:
+    serde_json::to_string_pretty(reports).unwrap()
+}
+
+/// A path as text, with anything that isn't valid UTF-8 replaced, since JSON can only hold
+/// text.
+fn lossy_path<S: Serializer>(path: &Path, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&path.to_string_lossy())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use crate::{Format, SubData};
+
+    use super::{check, exit_code, to_json, to_text, Report};
+
+    #[test]
+    fn test_check() {
+        let text = "1\n00:00:01,000 --> 00:00:03,000\nOne\n\n\
+                    1\n00:00:02,000 --> 00:00:02,000\n<i></i>\n\n\
+                    3\n-00:00:01,000 --> 00:00:04,000\r\nThis line is much too long to read in time, really\n\n\
+                    2\n00:00:05,000 --> 00:00:06,000\nFine\n";
+        let data = SubData::parse(text, Format::Srt, None).unwrap();
+        let findings: Vec<(&str, Option<i64>)> = check(text, &data)
+            .iter()
+            .map(|finding| (finding.kind, finding.subtitle))
+            .collect();
+        assert_eq!(
+            findings,
+            vec![
+                ("mixed-line-endings", None),
+                ("duplicate-number", Some(1)),
+                ("duration", Some(1)),
+                ("empty", Some(1)),
+                ("negative-time", Some(3)),
+                ("start-order", Some(3)),
+                ("line-length", Some(3)),
+                ("number-order", Some(2)),
+                ("overlap", Some(3)),
+                ("overlap", Some(3)),
+            ]
+        );
+
+        let clean = "1\n00:00:01,000 --> 00:00:02,000\nOne\n";
+        let data = SubData::parse(clean, Format::Srt, None).unwrap();
+        assert!(check(clean, &data).is_empty());
+
+        let wide = format!("1\n00:00:01,000 --> 00:00:04,000\n{}\n", "字".repeat(30));
+        let data = SubData::parse(&wide, Format::Srt, None).unwrap();
+        assert_eq!(check(&wide, &data)[0].kind, "line-length");
+    }
+
+    #[test]
+    fn test_output() {
+        let text = "00:00:02,000 --> 00:00:01,000\nOne\n";
+        let data = SubData::parse(text, Format::Srt, None).unwrap();
+        let mut reports = vec![Report {
+            file: PathBuf::from("a.srt"),
+            findings: check(text, &data),
+        }];
+        assert_eq!(
+            to_text(&reports),
+            "a.srt: warning: line 1, column 1: Missing subtitle number\n\
+             a.srt: error: subtitle 1: The subtitle ends before it starts: 00:00:02,000 --> 00:00:01,000\n\
+             Found 1 error and 1 warning in 1 file.\n"
+        );
+        assert_eq!(exit_code(&reports), 3);
+        let json: serde_json::Value = serde_json::from_str(&to_json(&reports)).unwrap();
+        assert_eq!(json[0]["file"], "a.srt");
+        assert_eq!(json[0]["findings"][0]["severity"], "warning");
+        assert_eq!(json[0]["findings"][0]["kind"], "syntax");
+        assert_eq!(json[0]["findings"][0]["line"], 1);
+        assert_eq!(json[0]["findings"][1]["severity"], "error");
+        assert_eq!(json[0]["findings"][1]["subtitle"], 1);
+        assert!(json[0]["findings"][1]["line"].is_null());
+
+        reports[0].findings.clear();
+        assert_eq!(exit_code(&reports), 0);
+        assert_eq!(to_text(&reports), "No problems found in 1 file.\n");
+        assert_eq!(
+            to_json(&reports),
+            "[\n  {\n    \"file\": \"a.srt\",\n    \"findings\": []\n  }\n]"
+        );
+
+        #[cfg(unix)]
+        {
+            use std::ffi::OsStr;
+            use std::os::unix::ffi::OsStrExt;
+
+            reports[0].file = PathBuf::from(OsStr::from_bytes(b"caf\xe9.srt"));
+            let json: serde_json::Value = serde_json::from_str(&to_json(&reports)).unwrap();
+            assert_eq!(json[0]["file"], "caf\u{fffd}.srt");
+        }
+    }
+}
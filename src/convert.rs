@@ -0,0 +1,407 @@
+//! Conversion between subtitle formats. Text markup is first translated into the SubRip style
+//! (`<i>`, `<b>`, `<u>`, `<font color>` and `{\anN}` tags), then into the target format.
+
+use std::collections::BTreeSet;
+
+use anyhow::{bail, Result};
+use lazy_static::lazy_static;
+use regex::{Captures, Regex};
+
+use crate::{vtt, Format, SubData};
+
+static ASS_HEADER: &[&str] = &[
+    "[Script Info]",
+    "ScriptType: v4.00+",
+    "PlayResX: 384",
+    "PlayResY: 288",
+    "ScaledBorderAndShadow: yes",
+    "",
+    "[V4+ Styles]",
+    "Format: Name, Fontname, Fontsize, PrimaryColour, SecondaryColour, OutlineColour, BackColour, \
+        Bold, Italic, Underline, StrikeOut, ScaleX, ScaleY, Spacing, Angle, BorderStyle, Outline, \
+        Shadow, Alignment, MarginL, MarginR, MarginV, Encoding",
+    "Style: Default,Arial,16,&H00FFFFFF,&H000000FF,&H00000000,&H00000000,0,0,0,0,100,100,0,0,1,1,0,\
+        2,10,10,10,1",
+    "",
+    "[Events]",
+    "Format: Layer, Start, End, Style, Name, MarginL, MarginR, MarginV, Effect, Text",
+];
+
+impl SubData {
+    /// Convert the subtitles to another format. Information that can't be represented in the
+    /// new format is dropped, and a description of it is returned.
+    pub fn convert(&mut self, to: Format) -> Result<Vec<String>> {
+        let from = self.format;
+        let mut warnings = BTreeSet::new();
+        if from == to {
+            return Ok(vec![]);
+        }
+        if to == Format::MicroDvd && self.fps.is_none() {
+            bail!("A frame rate is needed to write MicroDVD files. Use `--fps` to set one.");
+        }
+
+        if self.subs.iter().any(|sub| !sub.preceding.is_empty()) || !self.trailer.is_empty() {
+            warnings.insert("Comments and style blocks were removed.");
+        }
+        if from == Format::Ass {
+            warnings.insert("ASS styles, layers, actor names and margins were removed.");
+        }
+
+        for sub in self.subs.iter_mut() {
+            sub.preceding.clear();
+            sub.ass_fields.clear();
+
+            // Convert to SubRip style markup:
+            match from {
+                Format::Ass => {
+                    for line in sub.lines.iter_mut() {
+                        *line = ass_to_html(line, &mut warnings);
+                    }
+                }
+                Format::MicroDvd => microdvd_to_html(&mut sub.lines, &mut warnings),
+                Format::Vtt => {
+                    if sub
+                        .settings
+                        .iter()
+                        .flat_map(|s| s.split_whitespace())
+                        .any(is_top_setting)
+                    {
+                        if let Some(first_line) = sub.lines.first_mut() {
+                            first_line.insert_str(0, r"{\an8}");
+                        }
+                    } else if sub.settings.is_some() {
+                        warnings.insert("WebVTT cue settings were removed.");
+                    }
+                    if sub
+                        .identifier
+                        .as_ref()
+                        .is_some_and(|id| id.parse::<i64>().is_err())
+                    {
+                        warnings.insert("WebVTT cue identifiers were removed.");
+                    }
+                }
+                Format::Srt => {}
+            }
+            sub.settings = None;
+            sub.identifier = None;
+            if sub.position.take().is_some() {
+                warnings.insert("Pixel based positions (X1 X2 Y1 Y2) were removed.");
+            }
+
+            match to {
+                Format::Vtt => {
+                    sub.identifier = Some(sub.number.to_string());
+                    if let Some(first_line) = sub.lines.first_mut() {
+                        if let Some(line) = take_alignment(first_line) {
+                            vtt::set_line_setting(&mut sub.settings, Some(line));
+                        }
+                    }
+                }
+                Format::MicroDvd => {
+                    if let Some(first_line) = sub.lines.first_mut() {
+                        if take_alignment(first_line).is_some() {
+                            warnings.insert("Position tags like {\\an8} were removed.");
+                        }
+                    }
+                }
+                Format::Ass => {
+                    sub.ass_fields = ["0", "", "", "Default", "", "0", "0", "0", ""]
+                        .iter()
+                        .map(|field| field.to_string())
+                        .collect()
+                }
+                Format::Srt => {}
+            }
+
+            // Convert from SubRip style markup. Position tags were handled above.
+            for line in sub.lines.iter_mut() {
+                *line = html_to(to, line, &mut warnings);
+            }
+        }
+
+        self.header = match to {
+            Format::Srt => vec![],
+            Format::Vtt => vec![format!("WEBVTT{}", self.line_ending)],
+            Format::Ass => ASS_HEADER
+                .iter()
+                .map(|line| format!("{}{}", line, self.line_ending))
+                .collect(),
+            // Exact NTSC rates are written the way players expect, like 23.976:
+            Format::MicroDvd => vec![format!(
+                "{{1}}{{1}}{}{}",
+                (self.fps.unwrap() * 1000.0).round() / 1000.0,
+                self.line_ending
+            )],
+        };
+        self.trailer.clear();
+        self.format = to;
+        Ok(warnings.into_iter().map(String::from).collect())
+    }
+}
+
+/// Is this WebVTT cue setting a position at the top of the video?
+fn is_top_setting(setting: &str) -> bool {
+    match setting.strip_prefix("line:") {
+        // Line numbers count from the top unless they are negative.
+        Some(line) => match line.split(',').next().unwrap_or("") {
+            percent if percent.ends_with('%') => percent
+                .trim_end_matches('%')
+                .parse::<f64>()
+                .is_ok_and(|p| p < 30.0),
+            number => number.parse::<i64>().is_ok_and(|n| (0..3).contains(&n)),
+        },
+        None => false,
+    }
+}
+
+/// Remove a leading {\anN} tag and return the equivalent WebVTT line setting, if any.
+fn take_alignment(line: &mut String) -> Option<&'static str> {
+    lazy_static! {
+        static ref RE: Regex = Regex::new(r"^\{\\an(\d)\}").unwrap();
+    }
+    let captures = RE.captures(line)?;
+    let (tag_length, alignment) = (captures[0].len(), captures[1].parse::<u8>().ok()?);
+    line.replace_range(..tag_length, "");
+    match alignment {
+        7..=9 => Some("0"),
+        4..=6 => Some("50%"),
+        _ => None,
+    }
+}
+
+/// Translate ASS override tags into SubRip style tags.
+fn ass_to_html(line: &str, warnings: &mut BTreeSet<&'static str>) -> String {
+    lazy_static! {
+        static ref BLOCK: Regex = Regex::new(r"\{([^}]*)\}").unwrap();
+        static ref TAG: Regex = Regex::new(r"\\(\d?[a-z]+)([^\\]*)").unwrap();
+    }
+    let converted = BLOCK.replace_all(line, |block: &Captures| {
+        let mut html = String::new();
+        for tag in TAG.captures_iter(&block[1]) {
+            match (&tag[1], tag[2].trim()) {
+                ("i" | "b" | "u", "1") => html += &format!("<{}>", &tag[1]),
+                ("i" | "b" | "u", "0" | "") => html += &format!("</{}>", &tag[1]),
+                ("an", alignment) if block.get(0).unwrap().start() == 0 => {
+                    html += &format!("{{\\an{}}}", alignment)
+                }
+                ("c" | "1c", color) if color.starts_with("&H") => {
+                    html += &format!("<font color=\"#{}\">", bgr_to_rgb(color))
+                }
+                ("c" | "1c", "") => html += "</font>",
+                _ => {
+                    warnings.insert("ASS override tags that have no equivalent were removed.");
+                }
+            }
+        }
+        html
+    });
+    converted.replace("\\h", "\u{a0}").replace("\\n", " ")
+}
+
+/// Translate MicroDVD control codes like {y:i} into SubRip style tags. Lowercase codes apply to
+/// one line, uppercase codes apply to the whole subtitle.
+fn microdvd_to_html(lines: &mut [String], warnings: &mut BTreeSet<&'static str>) {
+    lazy_static! {
+        static ref CODE: Regex = Regex::new(r"\{([a-zA-Z]):([^}]*)\}").unwrap();
+    }
+    let mut cue_styles = String::new();
+    for line in lines.iter_mut() {
+        let mut styles = cue_styles.clone();
+        let text = CODE.replace_all(line, |code: &Captures| {
+            match &code[1] {
+                "y" => styles += &code[2],
+                "Y" => {
+                    cue_styles += &code[2];
+                    styles += &code[2];
+                }
+                _ => {
+                    warnings.insert("MicroDVD control codes that have no equivalent were removed.");
+                }
+            }
+            ""
+        });
+        let (text, ending) = split_ending(&text);
+        let mut open = String::new();
+        let mut close = String::new();
+        for style in ["b", "i", "u"] {
+            if styles.to_ascii_lowercase().contains(style) {
+                open += &format!("<{}>", style);
+                close.insert_str(0, &format!("</{}>", style));
+            }
+        }
+        *line = format!("{}{}{}{}", open, text, close, ending);
+    }
+}
+
+/// Translate SubRip style tags into the target format.
+fn html_to(to: Format, line: &str, warnings: &mut BTreeSet<&'static str>) -> String {
+    lazy_static! {
+        static ref TAG: Regex = Regex::new(r"<(/?)([A-Za-z]+|\d[\d:.]*)([^>]*)>").unwrap();
+        static ref COLOR: Regex = Regex::new(r#"color\s*=\s*"?#?([0-9A-Fa-f]{6})"#).unwrap();
+        static ref WHOLE_LINE: Regex =
+            Regex::new(r"^((?:<[biu]>)+)([^<]*)((?:</[biu]>)+)(\r?\n)?$").unwrap();
+    }
+    if to == Format::MicroDvd {
+        // MicroDVD can only style whole lines:
+        if let Some(captures) = WHOLE_LINE.captures(line) {
+            let styles: String = captures[1].chars().filter(|c| "biu".contains(*c)).collect();
+            return format!(
+                "{{y:{}}}{}{}",
+                styles,
+                &captures[2],
+                captures.get(4).map_or("", |m| m.as_str())
+            );
+        }
+    }
+
+    TAG.replace_all(line, |tag: &Captures| {
+        let closing = !tag[1].is_empty();
+        let name = tag[2].to_ascii_lowercase();
+        match (to, name.as_str()) {
+            (Format::Srt | Format::Vtt, "i" | "b" | "u") => tag[0].to_string(),
+            (Format::Ass, "i" | "b" | "u") => {
+                format!("{{\\{}{}}}", name, if closing { 0 } else { 1 })
+            }
+            (Format::Srt, "font") => tag[0].to_string(),
+            (Format::Ass, "font") if closing => r"{\c}".to_string(),
+            (Format::Ass, "font") if COLOR.is_match(&tag[3]) => {
+                format!(
+                    "{{\\c&H{}&}}",
+                    bgr_to_rgb(&COLOR.captures(&tag[3]).unwrap()[1])
+                )
+            }
+            (Format::Vtt, "c" | "v" | "lang" | "ruby" | "rt") => tag[0].to_string(),
+            (Format::Vtt, name) if name.starts_with(|c: char| c.is_ascii_digit()) => {
+                tag[0].to_string()
+            }
+            (_, "i" | "b" | "u" | "font" | "c" | "v" | "lang" | "ruby" | "rt") => {
+                warnings.insert("Formatting tags that have no equivalent were removed.");
+                String::new()
+            }
+            (_, name) if name.starts_with(|c: char| c.is_ascii_digit()) => {
+                warnings.insert("WebVTT karaoke timestamps were removed.");
+                String::new()
+            }
+            // Not a known tag, so it may be part of the text:
+            _ => tag[0].to_string(),
+        }
+    })
+    .to_string()
+}
+
+/// Swap the byte order of a color, from ASS's &HBBGGRR& to RRGGBB or the other way around.
+fn bgr_to_rgb(color: &str) -> String {
+    let hex: String = color.chars().filter(|c| c.is_ascii_hexdigit()).collect();
+    // ASS colors may have an alpha byte in front, so take the last 6 digits:
+    let hex = format!("{:0>6}", &hex[hex.len().saturating_sub(6)..]);
+    format!("{}{}{}", &hex[4..6], &hex[2..4], &hex[0..2]).to_ascii_uppercase()
+}
+
+/// Split a line into its text and its line ending.
+fn split_ending(line: &str) -> (&str, &str) {
+    let text = line.trim_end_matches(&['\r', '\n'][..]);
+    (text, &line[text.len()..])
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+
+    use crate::{Format, Position, SubData, TimeSpan};
+
+    use super::{ass_to_html, html_to, microdvd_to_html};
+
+    #[test]
+    fn test_ass_to_html() {
+        let mut warnings = BTreeSet::new();
+        assert_eq!(
+            ass_to_html(r"{\an8\i1}Hi{\i0} {\b1}there{\b0}", &mut warnings),
+            r"{\an8}<i>Hi</i> <b>there</b>"
+        );
+        assert!(warnings.is_empty());
+        assert_eq!(
+            ass_to_html(r"{\c&H0000FF&}red{\c}\hx", &mut warnings),
+            "<font color=\"#FF0000\">red</font>\u{a0}x"
+        );
+        assert_eq!(ass_to_html(r"{\fad(200,200)}Hi", &mut warnings), "Hi");
+        assert_eq!(warnings.len(), 1);
+    }
+
+    #[test]
+    fn test_microdvd_to_html() {
+        let mut warnings = BTreeSet::new();
+        let mut lines = vec!["{Y:i}One\n".to_string(), "{y:b}Two\n".to_string()];
+        microdvd_to_html(&mut lines, &mut warnings);
+        assert_eq!(lines, vec!["<i>One</i>\n", "<b><i>Two</i></b>\n"]);
+        assert!(warnings.is_empty());
+    }
+
+    #[test]
+    fn test_html_to() {
+        let mut warnings = BTreeSet::new();
+        let line = "<i>Hi</i> <font color=\"#FF0000\">red</font>\n";
+        assert_eq!(html_to(Format::Srt, line, &mut warnings), line);
+        assert_eq!(
+            html_to(Format::Ass, line, &mut warnings),
+            "{\\i1}Hi{\\i0} {\\c&H0000FF&}red{\\c}\n"
+        );
+        assert!(warnings.is_empty());
+        assert_eq!(html_to(Format::Vtt, line, &mut warnings), "<i>Hi</i> red\n");
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(
+            html_to(Format::MicroDvd, "<i><b>Hi</b></i>\n", &mut warnings),
+            "{y:ib}Hi\n"
+        );
+        assert_eq!(
+            html_to(Format::Srt, "1 < 2 > 0", &mut warnings),
+            "1 < 2 > 0"
+        );
+    }
+
+    #[test]
+    fn test_convert() {
+        let srt = "1\n00:00:01,000 --> 00:00:02,000  X1:1 X2:2 Y1:3 Y2:4\n{\\an8}<i>Top</i>\n\n\
+            2\n00:00:03,000 --> 00:00:04,500\nTwo\nlines\n\n";
+        let mut data = SubData::parse(srt, Format::Srt, None).unwrap();
+        let warnings = data.convert(Format::Vtt).unwrap();
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(
+            data.to_string(),
+            "WEBVTT\n\n\
+            1\n00:00:01.000 --> 00:00:02.000 line:0\n<i>Top</i>\n\n\
+            2\n00:00:03.000 --> 00:00:04.500\nTwo\nlines\n\n"
+        );
+
+        data.convert(Format::Ass).unwrap();
+        let ass = data.to_string();
+        assert!(ass.starts_with("[Script Info]\n"));
+        assert!(ass.ends_with(
+            "Dialogue: 0,0:00:01.00,0:00:02.00,Default,,0,0,0,,{\\an8}{\\i1}Top{\\i0}\n\
+            Dialogue: 0,0:00:03.00,0:00:04.50,Default,,0,0,0,,Two\\Nlines\n"
+        ));
+
+        let mut data = SubData::parse(&ass, Format::Ass, None).unwrap();
+        assert_eq!(data.subs[1].time_span, TimeSpan::new(3000, 4500));
+        assert!(data.convert(Format::MicroDvd).is_err());
+        data.fps = Some(25.0);
+        data.convert(Format::MicroDvd).unwrap();
+        assert_eq!(
+            data.to_string(),
+            "{1}{1}25\n{25}{50}{y:i}Top\n{75}{113}Two|lines\n"
+        );
+
+        data.convert(Format::Srt).unwrap();
+        assert!(data.subs[0].position.is_none());
+        data.subs[0].position = Some(Position {
+            x1: 1,
+            x2: 2,
+            y1: 3,
+            y2: 4,
+        });
+        assert_eq!(
+            data.to_string(),
+            "1\n00:00:01,000 --> 00:00:02,000  X1:1 X2:2 Y1:3 Y2:4\n<i>Top</i>\n\n\
+            2\n00:00:03,000 --> 00:00:04,500\nTwo\nlines\n\n"
+        );
+    }
+}
@@ -0,0 +1,240 @@
+//! A report of what would change, for `--dry-run`.
+
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use crate::ass::set_alignment;
+use crate::check::count;
+use crate::{Subtitle, TimeSpan};
+
+/// A table of the subtitles that changed, with their times before and after, followed by a
+/// summary. Subtitles are matched up by their index in the file, so subtitles that were
+/// removed or added get rows of their own, and are counted apart from those that changed.
+pub fn changes_table(before: &[Subtitle], after: &[Subtitle]) -> String {
+    // Each row is sorted by the index of the subtitle it belongs to, and subtitles that were
+    // added go after the one before them in the new order:
+    let mut rows: Vec<((usize, usize), String)> = vec![];
+    let positions: HashMap<usize, usize> = before
+        .iter()
+        .enumerate()
+        .filter_map(|(position, sub)| Some((sub.index?, position)))
+        .collect();
+    let mut matched = vec![false; before.len()];
+    let (mut changed, mut added, mut removed) = (0, 0, 0);
+    let mut last_index = 0;
+    for (position, new) in after.iter().enumerate() {
+        let order = position + 1;
+        let old = new
+            .index
+            .and_then(|index| positions.get(&index).copied())
+            .filter(|&i| !matched[i]);
+        let Some(i) = old else {
+            added += 1;
+            rows.push(((last_index, order), row("", None, Some(new), "added")));
+            continue;
+        };
+        matched[i] = true;
+        last_index = i;
+        let old = &before[i];
+        let other = other_changes(old, new);
+        if old.time_span != new.time_span || !other.is_empty() {
+            changed += 1;
+            let number = old.number.to_string();
+            rows.push((
+                (i, order),
+                row(&number, Some(old), Some(new), &other.join(", ")),
+            ));
+        }
+    }
+    for (i, old) in before.iter().enumerate().filter(|&(i, _)| !matched[i]) {
+        removed += 1;
+        rows.push((
+            (i, 0),
+            row(&old.number.to_string(), Some(old), None, "removed"),
+        ));
+    }
+    rows.sort_by_key(|(key, _)| *key);
+
+    let mut table = String::new();
+    if !rows.is_empty() {
+        writeln!(
+            table,
+            "{:>5}  {:<30}  {:<30}  {:>7}  {:>7}  Other",
+            "#", "Before", "After", "Start", "End"
+        )
+        .unwrap();
+        for (_, row) in &rows {
+            writeln!(table, "{}", row).unwrap();
+        }
+        table.push('\n');
+    }
+    writeln!(
+        table,
+        "{} of {} would change.",
+        changed,
+        count(before.len(), "subtitle")
+    )
+    .unwrap();
+    for (n, what) in [(removed, "removed"), (added, "added")] {
+        if n > 0 {
+            writeln!(table, "{} would be {}.", count(n, "subtitle"), what).unwrap();
+        }
+    }
+    if after.len() != before.len() {
+        writeln!(
+            table,
+            "There would be {} instead of {}.",
+            count(after.len(), "subtitle"),
+            before.len()
+        )
+        .unwrap();
+    }
+    let negative = after
+        .iter()
+        .filter(|sub| sub.time_span.start_ms < 0)
+        .count();
+    if negative > 0 {
+        writeln!(
+            table,
+            "Warning: {} would start before the video does.",
+            count(negative, "subtitle")
+        )
+        .unwrap();
+    }
+    table
+}
+
+/// A row of the table. The times are left empty for a subtitle that was added or removed.
+fn row(number: &str, old: Option<&Subtitle>, new: Option<&Subtitle>, other: &str) -> String {
+    let span = |sub: Option<&Subtitle>| sub.map_or(String::new(), |sub| sub.time_span.to_string());
+    let difference = |time: fn(&TimeSpan) -> i64| match (old, new) {
+        (Some(old), Some(new)) => difference(&old.time_span, &new.time_span, time),
+        _ => String::new(),
+    };
+    let row = format!(
+        "{:>5}  {:<30}  {:<30}  {:>7}  {:>7}  {}",
+        number,
+        span(old),
+        span(new),
+        difference(|span| span.start_ms),
+        difference(|span| span.end_ms),
+        other
+    );
+    row.trim_end().to_string()
+}
+
+/// How far a time moved, in seconds, like `+1.500`.
+fn difference(old: &TimeSpan, new: &TimeSpan, time: impl Fn(&TimeSpan) -> i64) -> String {
+    let ms = time(new) - time(old);
+    if ms == 0 {
+        String::new()
+    } else {
+        format!(
+            "{}{}.{:03}",
+            if ms < 0 { "-" } else { "+" },
+            ms.abs() / 1000,
+            ms.abs() % 1000
+        )
+    }
+}
+
+/// Changes other than timing, like a new position.
+fn other_changes(old: &Subtitle, new: &Subtitle) -> Vec<String> {
+    let mut changes = vec![];
+    if old.number != new.number {
+        changes.push(format!("renumbered to {}", new.number));
+    }
+    // Alignment tags at the start of a line are how most formats position subtitles:
+    let without_alignment = |sub: &Subtitle| -> Vec<String> {
+        sub.lines
+            .iter()
+            .map(|line| set_alignment(line, None, false))
+            .collect()
+    };
+    if old.position != new.position || old.settings != new.settings || old.lines != new.lines {
+        if without_alignment(old) == without_alignment(new) {
+            changes.push("position".to_string());
+        } else {
+            changes.push("text".to_string());
+        }
+    }
+    changes
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_subs::sub;
+    use crate::{Position, TimeSpan};
+
+    use super::changes_table;
+
+    #[test]
+    fn test_changes_table() {
+        let mut before = vec![
+            sub(1, 1000, 2000, "One"),
+            sub(2, 3000, 4000, "Two"),
+            sub(3, 5000, 6000, "Three"),
+        ];
+        for (i, sub) in before.iter_mut().enumerate() {
+            sub.index = Some(i);
+        }
+        let mut after = before.clone();
+        after[0].time_span = TimeSpan::new(-500, 500);
+        after[2].lines = vec!["{\\an8}Three\n".to_string()];
+
+        assert_eq!(
+            changes_table(&before, &after),
+            "    #  Before                          After                             Start      End  Other\n\
+             \x20   1  00:00:01,000 --> 00:00:02,000   -00:00:00,500 --> 00:00:00,500   -1.500   -1.500\n\
+             \x20   3  00:00:05,000 --> 00:00:06,000   00:00:05,000 --> 00:00:06,000                     position\n\
+             \n\
+             2 of 3 subtitles would change.\n\
+             Warning: 1 subtitle would start before the video does.\n"
+        );
+
+        after[2].lines = vec!["Four\n".to_string()];
+        after[1].position = Some(Position {
+            x1: 0,
+            x2: 0,
+            y1: 0,
+            y2: 0,
+        });
+        after[1].number = 5;
+        let table = changes_table(&before, &after);
+        assert!(table.contains("renumbered to 5, position\n"));
+        assert!(table.contains("  text\n"));
+
+        assert_eq!(
+            changes_table(&before, &before),
+            "0 of 3 subtitles would change.\n"
+        );
+
+        // Subtitles that were removed or added aren't compared with other subtitles:
+        let after = vec![
+            before[0].clone(),
+            sub(2, 2000, 2500, "Added"),
+            before[2].clone(),
+        ];
+        assert_eq!(
+            changes_table(&before, &after),
+            "    #  Before                          After                             Start      End  Other\n\
+             \x20                                      00:00:02,000 --> 00:00:02,500                     added\n\
+             \x20   2  00:00:03,000 --> 00:00:04,000                                                     removed\n\
+             \n\
+             0 of 3 subtitles would change.\n\
+             1 subtitle would be removed.\n\
+             1 subtitle would be added.\n"
+        );
+
+        assert_eq!(
+            changes_table(&before, &after[..1]),
+            "    #  Before                          After                             Start      End  Other\n\
+             \x20   2  00:00:03,000 --> 00:00:04,000                                                     removed\n\
+             \x20   3  00:00:05,000 --> 00:00:06,000                                                     removed\n\
+             \n\
+             0 of 3 subtitles would change.\n\
+             2 subtitles would be removed.\n\
+             There would be 1 subtitle instead of 3.\n"
+        );
+    }
+}
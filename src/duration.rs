@@ -0,0 +1,150 @@
+//! Keeping subtitles on screen long enough to read, and not too long, for `--min-duration`,
+//! `--max-duration` and `--max-cps`.
+
+use crate::check::plain_text;
+use crate::Subtitle;
+
+/// How long subtitles may be shown. Each limit is optional.
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub struct Limits {
+    pub min_ms: Option<i64>,
+    pub max_ms: Option<i64>,
+    /// The most characters per second that viewers are expected to read.
+    pub max_cps: Option<f64>,
+}
+
+impl Limits {
+    pub fn is_empty(&self) -> bool {
+        self.min_ms.is_none() && self.max_ms.is_none() && self.max_cps.is_none()
+    }
+
+    /// The shortest time the subtitle should be shown for.
+    fn needed_ms(&self, sub: &Subtitle) -> i64 {
+        let characters: usize = sub
+            .lines
+            .iter()
+            .map(|line| plain_text(line).chars().count())
+            .sum();
+        let reading_ms = self
+            .max_cps
+            .map_or(0, |cps| (characters as f64 / cps * 1000.0).ceil() as i64);
+        reading_ms.max(self.min_ms.unwrap_or(0))
+    }
+}
+
+/// Make subtitles that are too short end later, as far as the next subtitle allows with
+/// `min_gap_ms` between them, and make subtitles that are too long end sooner. The subtitles
+/// don't need to be in time order. A warning is returned for each subtitle that is still too
+/// short.
+pub fn enforce(subs: &mut [Subtitle], limits: Limits, min_gap_ms: i64) -> Vec<String> {
+    let mut starts: Vec<(i64, i64)> = subs
+        .iter()
+        .map(|sub| (sub.time_span.start_ms, sub.number))
+        .collect();
+    starts.sort_unstable();
+    let mut warnings = vec![];
+    for sub in subs {
+        let needed_ms = limits.needed_ms(sub);
+        let span = &sub.time_span;
+        let next = starts
+            .get(starts.partition_point(|&(start_ms, _)| start_ms <= span.start_ms))
+            .copied();
+        let wanted_end = span.start_ms + needed_ms;
+        let latest_end = next.map_or(i64::MAX, |(start_ms, _)| start_ms - min_gap_ms);
+        let mut end_ms = span.end_ms;
+        if end_ms < wanted_end {
+            end_ms = wanted_end.min(latest_end).max(end_ms);
+        }
+        if let Some(max_ms) = limits.max_ms {
+            end_ms = end_ms.min(span.start_ms + max_ms);
+        }
+
+        if end_ms - span.start_ms < needed_ms {
+            let reason = match (limits.max_ms, next) {
+                (Some(max_ms), _) if needed_ms > max_ms => format!(
+                    "the longest time allowed is {:.3} seconds",
+                    max_ms as f64 / 1000.0
+                ),
+                (_, Some((_, number))) => format!("subtitle {} starts then", number),
+                (_, None) => "it can't be made longer".to_string(),
+            };
+            warnings.push(format!(
+                "Subtitle {} is shown for {:.3} seconds but needs {:.3}, and {}.",
+                sub.number,
+                (end_ms - span.start_ms) as f64 / 1000.0,
+                needed_ms as f64 / 1000.0,
+                reason
+            ));
+        }
+        sub.time_span.end_ms = end_ms;
+    }
+    warnings
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_subs::sub;
+    use crate::TimeSpan;
+
+    use super::{enforce, Limits};
+
+    #[test]
+    fn test_enforce() {
+        let mut subs = vec![
+            sub(1, 1000, 1200, "Hi"),
+            sub(2, 2000, 2500, "<i>Eighteen letters!!</i>"),
+            sub(3, 2900, 9000, "Long"),
+            sub(4, 20000, 20300, "Last"),
+        ];
+        let limits = Limits {
+            min_ms: Some(700),
+            max_ms: Some(5000),
+            max_cps: Some(10.0),
+        };
+        let warnings = enforce(&mut subs, limits, 100);
+        let spans: Vec<TimeSpan> = subs.iter().map(|sub| sub.time_span.clone()).collect();
+        assert_eq!(
+            spans,
+            vec![
+                TimeSpan::new(1000, 1700),
+                TimeSpan::new(2000, 2800),
+                TimeSpan::new(2900, 7900),
+                TimeSpan::new(20000, 20700),
+            ]
+        );
+        assert_eq!(
+            warnings,
+            vec!["Subtitle 2 is shown for 0.800 seconds but needs 1.800, and subtitle 3 starts then."]
+        );
+
+        let mut subs = vec![sub(1, 0, 1000, &"x".repeat(100))];
+        let warnings = enforce(&mut subs, limits, 0);
+        assert_eq!(subs[0].time_span, TimeSpan::new(0, 5000));
+        assert_eq!(
+            warnings,
+            vec!["Subtitle 1 is shown for 5.000 seconds but needs 10.000, and the longest time allowed is 5.000 seconds."]
+        );
+        assert!(Limits::default().is_empty());
+
+        // Subtitles out of time order are only made longer up to the one that starts next:
+        let mut subs = vec![
+            sub(1, 10000, 10200, "One"),
+            sub(2, 14000, 15000, "Two"),
+            sub(3, 10500, 11000, "Three"),
+        ];
+        let limits = Limits {
+            min_ms: Some(3000),
+            ..Default::default()
+        };
+        enforce(&mut subs, limits, 0);
+        let spans: Vec<TimeSpan> = subs.iter().map(|sub| sub.time_span.clone()).collect();
+        assert_eq!(
+            spans,
+            vec![
+                TimeSpan::new(10000, 10500),
+                TimeSpan::new(14000, 17000),
+                TimeSpan::new(10500, 13500),
+            ]
+        );
+    }
+}
@@ -0,0 +1,385 @@
+//! Reading and writing subtitle files in their original text encoding. Many older subtitles
+//! use a legacy code page, and some players can't read anything else.
+
+use std::fmt;
+
+use anyhow::{anyhow, Result};
+use chardetng::EncodingDetector;
+use encoding_rs::{EncoderResult, Encoding, UTF_16BE, UTF_16LE, UTF_8};
+use log::{info, warn};
+
+/// The encoding of a file, and whether it starts with a byte order mark.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct TextEncoding {
+    pub encoding: &'static Encoding,
+    pub bom: bool,
+}
+
+impl Default for TextEncoding {
+    fn default() -> Self {
+        Self {
+            encoding: UTF_8,
+            bom: false,
+        }
+    }
+}
+
+/// How sure the encoding detection is.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Confidence {
+    /// From a byte order mark, or text that's ASCII or valid UTF-8.
+    Certain,
+    /// The statistical guess stood out from the others, and the text looks like words.
+    Likely,
+    /// The text may come out garbled.
+    Unsure,
+}
+
+/// The encoding that was chosen for a file, and why.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Detection {
+    pub encoding: TextEncoding,
+    pub confidence: Confidence,
+    pub reason: &'static str,
+    /// How many of the non-ASCII characters decode to letters, if there are any. Garbled
+    /// text has lots of symbols and control characters instead.
+    pub letters: Option<f64>,
+}
+
+impl fmt::Display for Detection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let confidence = match self.confidence {
+            Confidence::Certain => "certain",
+            Confidence::Likely => "likely",
+            Confidence::Unsure => "unsure",
+        };
+        write!(
+            f,
+            "{} ({}, {}",
+            self.encoding.encoding.name(),
+            confidence,
+            self.reason
+        )?;
+        if let Some(letters) = self.letters {
+            write!(
+                f,
+                "; {:.0}% of the non-ASCII characters are letters",
+                letters * 100.0
+            )?;
+        }
+        f.write_str(")")
+    }
+}
+
+/// Work out the encoding of a file. Byte order marks and UTF-8 are recognized for certain.
+/// Anything else is guessed from how often each byte appears, which works for the legacy
+/// code pages of most languages, including Cyrillic, Central European, Greek, Arabic,
+/// Hebrew, Chinese, Japanese and Korean.
+pub fn detect(bytes: &[u8]) -> Detection {
+    detect_and_decode(bytes).0
+}
+
+/// Work out the encoding of a file like `detect`, and decode it with that encoding.
+fn detect_and_decode(bytes: &[u8]) -> (Detection, String) {
+    let detection = |encoding, bom, confidence, reason| {
+        let (text, _) = decode_with(bytes, encoding);
+        let detection = Detection {
+            encoding: TextEncoding { encoding, bom },
+            confidence,
+            reason,
+            letters: letters(&text),
+        };
+        (detection, text)
+    };
+    if let Some((encoding, _)) = Encoding::for_bom(bytes) {
+        return detection(encoding, true, Confidence::Certain, "byte order mark");
+    } else if bytes.is_ascii() {
+        return detection(UTF_8, false, Confidence::Certain, "only ASCII text");
+    } else if std::str::from_utf8(bytes).is_ok() {
+        return detection(UTF_8, false, Confidence::Certain, "valid UTF-8");
+    }
+
+    let mut detector = EncodingDetector::new();
+    detector.feed(bytes, true);
+    let (encoding, stands_out) = detector.guess_assess(None, false);
+    let (mut detection, text) = detection(encoding, false, Confidence::Unsure, "statistical guess");
+    if stands_out && detection.letters.unwrap_or(1.0) >= 0.9 {
+        detection.confidence = Confidence::Likely;
+    }
+    (detection, text)
+}
+
+/// The share of non-ASCII characters that are letters, if there are any.
+fn letters(text: &str) -> Option<f64> {
+    let non_ascii = text.chars().filter(|c| !c.is_ascii());
+    let (count, letters) = non_ascii.fold((0, 0), |(count, letters), c| {
+        (count + 1, letters + c.is_alphabetic() as usize)
+    });
+    (count > 0).then(|| letters as f64 / count as f64)
+}
+
+/// Parse an encoding name for `--input-encoding`, like windows-1251 or Shift_JIS.
+pub fn parse_input_encoding(label: &str) -> Result<&'static Encoding> {
+    Encoding::for_label(label.trim().as_bytes())
+        .ok_or_else(|| anyhow!("Unknown text encoding: {:#?}", label))
+}
+
+/// Parse an encoding name for `--output-encoding`, like windows-1250 or UTF-16LE. UTF-16 is
+/// written with a byte order mark, and so is UTF-8 if it's given as `utf-8-bom`.
+pub fn parse_encoding(label: &str) -> Result<TextEncoding> {
+    if label.eq_ignore_ascii_case("utf-8-bom") || label.eq_ignore_ascii_case("utf8-bom") {
+        return Ok(TextEncoding {
+            encoding: UTF_8,
+            bom: true,
+        });
+    }
+    let encoding = parse_input_encoding(label)?;
+    Ok(TextEncoding {
+        encoding,
+        bom: encoding == UTF_16LE || encoding == UTF_16BE,
+    })
+}
+
+/// Decode a file in the given encoding, or in the detected one. Invalid characters are
+/// replaced.
+pub fn decode(bytes: &[u8], encoding: Option<&'static Encoding>) -> (String, TextEncoding) {
+    let (text, encoding) = match encoding {
+        Some(encoding) => decode_with(bytes, encoding),
+        None => {
+            let (detection, text) = detect_and_decode(bytes);
+            if detection.confidence == Confidence::Certain {
+                info!("The text encoding is {}", detection);
+            } else {
+                warn!(
+                    "Guessed that the text encoding is {}. If the text looks wrong, use `--input-encoding`.",
+                    detection
+                );
+            }
+            (text, detection.encoding)
+        }
+    };
+    if text.contains('\u{fffd}') {
+        warn!(
+            "The file has characters that aren't valid {}. They were replaced.",
+            encoding.encoding.name()
+        );
+    }
+    (text, encoding)
+}
+
+/// Decode a file in an encoding, skipping a byte order mark for that encoding.
+fn decode_with(bytes: &[u8], encoding: &'static Encoding) -> (String, TextEncoding) {
+    let bom_length = match Encoding::for_bom(bytes) {
+        Some((bom_encoding, length)) if bom_encoding == encoding => length,
+        _ => 0,
+    };
+    let (text, _) = encoding.decode_without_bom_handling(&bytes[bom_length..]);
+    (
+        text.into_owned(),
+        TextEncoding {
+            encoding,
+            bom: bom_length > 0,
+        },
+    )
+}
+
+/// Encode text for writing. Characters the encoding can't represent are written as `?`.
+pub fn encode(text: &str, encoding: TextEncoding) -> Vec<u8> {
+    let mut bytes = vec![];
+    if encoding.encoding == UTF_16LE || encoding.encoding == UTF_16BE {
+        // encoding_rs only decodes UTF-16, so it's encoded here:
+        if encoding.bom {
+            bytes.extend_from_slice(&u16_bytes(encoding.encoding)(0xFEFF));
+        }
+        for unit in text.encode_utf16() {
+            bytes.extend_from_slice(&u16_bytes(encoding.encoding)(unit));
+        }
+        return bytes;
+    }
+
+    if encoding.bom && encoding.encoding == UTF_8 {
+        bytes.extend_from_slice(b"\xEF\xBB\xBF");
+    }
+    let mut encoder = encoding.encoding.new_encoder();
+    let mut rest = text;
+    let mut unmappable = 0;
+    loop {
+        bytes.reserve(rest.len() + 16);
+        let (result, read) =
+            encoder.encode_from_utf8_to_vec_without_replacement(rest, &mut bytes, true);
+        rest = &rest[read..];
+        match result {
+            EncoderResult::InputEmpty => break,
+            EncoderResult::OutputFull => (),
+            EncoderResult::Unmappable(_) => {
+                unmappable += 1;
+                bytes.push(b'?');
+            }
+        }
+    }
+    if unmappable > 0 {
+        warn!(
+            "{} characters can't be written in {} and were replaced with \"?\".",
+            unmappable,
+            encoding.encoding.name()
+        );
+    }
+    bytes
+}
+
+fn u16_bytes(encoding: &'static Encoding) -> fn(u16) -> [u8; 2] {
+    if encoding == UTF_16LE {
+        u16::to_le_bytes
+    } else {
+        u16::to_be_bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use encoding_rs::{
+        BIG5, SHIFT_JIS, UTF_16BE, UTF_16LE, UTF_8, WINDOWS_1250, WINDOWS_1251, WINDOWS_1253,
+        WINDOWS_1255,
+    };
+
+    use super::{decode, detect, encode, parse_encoding, Confidence, TextEncoding};
+
+    #[test]
+    fn test_decode() {
+        assert_eq!(
+            decode("čas\n".as_bytes(), None),
+            ("čas\n".to_string(), TextEncoding::default())
+        );
+        assert_eq!(
+            decode(b"\xEF\xBB\xBFhi", None),
+            (
+                "hi".to_string(),
+                TextEncoding {
+                    encoding: UTF_8,
+                    bom: true
+                }
+            )
+        );
+        assert_eq!(
+            decode(b"\xFF\xFEh\0i\0", None),
+            (
+                "hi".to_string(),
+                TextEncoding {
+                    encoding: UTF_16LE,
+                    bom: true
+                }
+            )
+        );
+    }
+
+    #[test]
+    fn test_detect() {
+        let legacy = |text: &str, encoding| {
+            (
+                encode(
+                    text,
+                    TextEncoding {
+                        encoding,
+                        bom: false,
+                    },
+                ),
+                encoding,
+            )
+        };
+        for (bytes, encoding) in [
+            legacy(
+                "1\n00:00:01,000 --> 00:00:02,000\nГде ты был всё это время?\nЯ искал тебя повсюду, но никто не знал, куда ты ушёл.\n",
+                WINDOWS_1251,
+            ),
+            legacy(
+                "1\n00:00:01,000 --> 00:00:02,000\nNechci, abys odešel. Řekni mi, že zůstaneš.\nVšechno bude v pořádku, uvidíš. Příští týden přijede můj bratr.\n",
+                WINDOWS_1250,
+            ),
+            legacy(
+                "1\n00:00:01,000 --> 00:00:02,000\nΠού ήσουν όλη αυτή την ώρα;\nΣε έψαχνα παντού, αλλά κανείς δεν ήξερε πού πήγες.\n",
+                WINDOWS_1253,
+            ),
+            legacy(
+                "1\n00:00:01,000 --> 00:00:02,000\nאיפה היית כל הזמן הזה?\nחיפשתי אותך בכל מקום, אבל אף אחד לא ידע לאן הלכת.\n",
+                WINDOWS_1255,
+            ),
+            legacy(
+                "1\n00:00:01,000 --> 00:00:02,000\nずっとどこにいたの？\nあちこち探したけど、誰もあなたがどこに行ったか知らなかった。\n",
+                SHIFT_JIS,
+            ),
+            legacy(
+                "1\n00:00:01,000 --> 00:00:02,000\n你這段時間都去哪裡了？\n我到處找你，但是沒有人知道你去了哪裡。\n",
+                BIG5,
+            ),
+        ] {
+            let detection = detect(&bytes);
+            assert_eq!(detection.encoding.encoding, encoding);
+            assert_eq!(detection.confidence, Confidence::Likely, "{}", detection);
+            assert!(detection.to_string().starts_with(&format!("{} (likely, ", encoding.name())));
+        }
+
+        let detection = detect("Všechno bude v pořádku.".as_bytes());
+        assert_eq!(detection.encoding.encoding, UTF_8);
+        assert_eq!(detection.confidence, Confidence::Certain);
+        assert_eq!(
+            detection.to_string(),
+            "UTF-8 (certain, valid UTF-8; 100% of the non-ASCII characters are letters)"
+        );
+        assert_eq!(
+            detect(b"Hi").to_string(),
+            "UTF-8 (certain, only ASCII text)"
+        );
+        assert_eq!(detect(b"x\xD7 \xF7y").confidence, Confidence::Unsure);
+
+        // A given encoding is used even if it doesn't look right:
+        assert_eq!(decode(b"\xE8as", Some(WINDOWS_1251)).0, "иas");
+        assert_eq!(
+            decode(b"\xEF\xBB\xBFhi", Some(UTF_8)),
+            decode(b"\xEF\xBB\xBFhi", None)
+        );
+    }
+
+    #[test]
+    fn test_encode() {
+        let encoding = |encoding, bom| TextEncoding { encoding, bom };
+        assert_eq!(encode("čas", encoding(WINDOWS_1250, false)), b"\xE8as");
+        assert_eq!(
+            encode("čas €", encoding(WINDOWS_1250, false)),
+            b"\xE8as \x80"
+        );
+        assert_eq!(encode("日本", encoding(WINDOWS_1250, false)), b"??");
+        assert_eq!(
+            encode("日本", encoding(SHIFT_JIS, false)),
+            b"\x93\xfa\x96\x7b"
+        );
+        assert_eq!(encode("hi", encoding(UTF_8, true)), b"\xEF\xBB\xBFhi");
+        assert_eq!(encode("hi", encoding(UTF_16LE, true)), b"\xFF\xFEh\0i\0");
+        assert_eq!(encode("hi", encoding(UTF_16BE, false)), b"\0h\0i");
+
+        for text in ["1\r\n00:00:01,000 --> 00:00:02,000\r\nčas ž\r\n", "日本語"] {
+            for bytes in [
+                encode(text, encoding(UTF_8, true)),
+                encode(text, encoding(UTF_16LE, true)),
+                encode(text, encoding(UTF_16BE, true)),
+            ] {
+                assert_eq!(decode(&bytes, None).0, text);
+            }
+        }
+    }
+
+    #[test]
+    fn test_parse_encoding() {
+        assert_eq!(parse_encoding("cp1250").unwrap().encoding, WINDOWS_1250);
+        assert_eq!(parse_encoding("Shift_JIS").unwrap().encoding, SHIFT_JIS);
+        assert_eq!(
+            parse_encoding("UTF-16LE").unwrap(),
+            TextEncoding {
+                encoding: UTF_16LE,
+                bom: true
+            }
+        );
+        assert!(parse_encoding("utf-8-bom").unwrap().bom);
+        assert!(!parse_encoding("utf-8").unwrap().bom);
+        assert!(parse_encoding("klingon").is_err());
+    }
+}
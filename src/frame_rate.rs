@@ -0,0 +1,167 @@
+use std::fmt;
+use std::str::FromStr;
+
+use anyhow::{anyhow, bail, Error, Result};
+
+/// A video frame rate, kept as an exact fraction. The NTSC rates like 23.976 are really
+/// 24000/1001, and the difference adds up over a whole movie.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct FrameRate {
+    num: u64,
+    den: u64,
+}
+
+impl FrameRate {
+    /// Film, and most Blu-ray releases.
+    pub const FILM: FrameRate = FrameRate { num: 24, den: 1 };
+    /// Film slowed down for NTSC video, 23.976 fps.
+    pub const NTSC: FrameRate = FrameRate {
+        num: 24000,
+        den: 1001,
+    };
+    /// Film sped up for PAL video.
+    pub const PAL: FrameRate = FrameRate { num: 25, den: 1 };
+
+    pub fn new(num: u64, den: u64) -> Result<Self> {
+        if num == 0 || den == 0 {
+            bail!("The frame rate must be a positive number.");
+        }
+        let divisor = gcd(num, den);
+        Ok(Self {
+            num: num / divisor,
+            den: den / divisor,
+        })
+    }
+
+    pub fn as_f64(self) -> f64 {
+        self.num as f64 / self.den as f64
+    }
+
+    /// How much faster this frame rate is than the other one. Subtitles timed for a video at
+    /// this rate need this scale to fit the same video at the other rate.
+    pub fn ratio(self, other: FrameRate) -> f64 {
+        (self.num as f64 * other.den as f64) / (self.den as f64 * other.num as f64)
+    }
+}
+
+fn gcd(a: u64, b: u64) -> u64 {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+impl FromStr for FrameRate {
+    type Err = Error;
+
+    /// Parse a name like `pal`, a fraction like `24000/1001`, or a number. Numbers that are
+    /// rounded NTSC rates, like 23.976, 29.97 or 59.94, are read as the exact NTSC rate.
+    fn from_str(input: &str) -> Result<Self> {
+        let input = input.trim().to_ascii_lowercase();
+        let invalid = || {
+            anyhow!(
+                "Expecting a frame rate like 25, 23.976, 24000/1001, film, ntsc or pal; got: {:#?}",
+                input
+            )
+        };
+        match input.as_str() {
+            "film" => return Ok(Self::FILM),
+            "ntsc" => return Ok(Self::NTSC),
+            "pal" => return Ok(Self::PAL),
+            _ => (),
+        }
+
+        if let Some((num, den)) = input.split_once('/') {
+            let num = num.trim().parse().map_err(|_| invalid())?;
+            let den = den.trim().parse().map_err(|_| invalid())?;
+            return Self::new(num, den);
+        }
+
+        let (whole, fraction) = input.split_once('.').unwrap_or((&input, ""));
+        if !fraction.chars().all(|c| c.is_ascii_digit()) {
+            return Err(invalid());
+        }
+        let whole: u64 = if whole.is_empty() {
+            0
+        } else {
+            whole.parse().map_err(|_| invalid())?
+        };
+        let den = 10_u64
+            .checked_pow(fraction.len() as u32)
+            .ok_or_else(invalid)?;
+        let num = whole
+            .checked_mul(den)
+            .and_then(|num| num.checked_add(fraction.parse().unwrap_or(0)))
+            .ok_or_else(invalid)?;
+        let rate = Self::new(num, den)?;
+
+        let ntsc = rate.as_f64() * 1.001;
+        if rate.den != 1 && (ntsc - ntsc.round()).abs() < 0.005 {
+            return Self::new(ntsc.round() as u64 * 1000, 1001);
+        }
+        Ok(rate)
+    }
+}
+
+impl fmt::Display for FrameRate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.den == 1 {
+            write!(f, "{}", self.num)
+        } else {
+            write!(f, "{}/{}", self.num, self.den)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::FrameRate;
+
+    #[test]
+    fn test_parse_frame_rate() {
+        let parse = |input: &str| input.parse::<FrameRate>().unwrap();
+        assert_eq!(parse("film"), FrameRate::new(24, 1).unwrap());
+        assert_eq!(parse("PAL"), FrameRate::new(25, 1).unwrap());
+        assert_eq!(parse("ntsc"), FrameRate::new(24000, 1001).unwrap());
+        assert_eq!(parse("23.976"), FrameRate::NTSC);
+        assert_eq!(parse("23.98"), FrameRate::NTSC);
+        assert_eq!(parse("24000/1001"), FrameRate::NTSC);
+        assert_eq!(parse("29.97"), FrameRate::new(30000, 1001).unwrap());
+        assert_eq!(parse("59.94"), FrameRate::new(60000, 1001).unwrap());
+        assert_eq!(parse("30"), FrameRate::new(30, 1).unwrap());
+        assert_eq!(parse("50.0"), FrameRate::new(50, 1).unwrap());
+        assert_eq!(parse("12.5"), FrameRate::new(25, 2).unwrap());
+        assert_eq!(parse("48/2"), FrameRate::FILM);
+
+        for input in [
+            "",
+            "0",
+            "-25",
+            "25fps",
+            "1/0",
+            "x/2",
+            "1.2.3",
+            "99999999999999999999",
+        ] {
+            assert!(input.parse::<FrameRate>().is_err(), "{:?}", input);
+        }
+    }
+
+    #[test]
+    fn test_frame_rate_ratio() {
+        assert_eq!(
+            FrameRate::PAL.ratio(FrameRate::NTSC),
+            25.0 * 1001.0 / 24000.0
+        );
+        assert_eq!(FrameRate::FILM.ratio(FrameRate::NTSC), 1.001);
+        assert_eq!(
+            "30".parse::<FrameRate>()
+                .unwrap()
+                .ratio("29.97".parse().unwrap()),
+            1.001
+        );
+        assert_eq!(FrameRate::NTSC.to_string(), "24000/1001");
+        assert_eq!(FrameRate::NTSC.as_f64(), 24000.0 / 1001.0);
+    }
+}
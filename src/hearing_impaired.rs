@@ -0,0 +1,121 @@
+//! Removing the text that's only meant for deaf and hard of hearing viewers, like sound
+//! descriptions and speaker names, for `--strip-hi`.
+
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use lazy_static::lazy_static;
+use log::info;
+use regex::Regex;
+
+use crate::check::plain_text;
+use crate::SubData;
+
+lazy_static! {
+    /// Sound descriptions like `[door slams]` or `(MUSIC)`, which may span lines.
+    static ref DESCRIPTION: Regex = Regex::new(r"\[[^\]]*\]|\([^)]*\)").unwrap();
+    /// Speaker labels like `JOHN:` or `- DR. SMITH:`, after any formatting tags and dialogue dash.
+    static ref SPEAKER: Regex = Regex::new(
+        r"(?m)^((?:-\s*)?(?:<[^>]*>|\{[^}]*\})*(?:-\s*)?)[A-Z][A-Z0-9 .'-]*:\s*"
+    )
+    .unwrap();
+    static ref SPACES: Regex = Regex::new(r"[ \t]{2,}").unwrap();
+}
+
+/// Read extra patterns to remove from a file, one regular expression per line. Blank lines
+/// and lines starting with `#` are skipped.
+pub fn read_patterns(path: &Path) -> Result<Vec<Regex>> {
+    let text = std::fs::read_to_string(path)
+        .with_context(|| format!("Can't read the patterns file {:#?}", path))?;
+    text.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty() && !line.starts_with('#'))
+        .map(|(i, line)| {
+            Regex::new(line)
+                .with_context(|| format!("Invalid pattern on line {} of {:#?}", i + 1, path))
+        })
+        .collect()
+}
+
+/// Remove sound descriptions, lines with music notes, speaker labels, and anything matching
+/// the extra patterns. Subtitles with no text left are removed.
+pub fn strip(data: &mut SubData, patterns: &[Regex]) {
+    let line_ending = data.line_ending.clone();
+    let mut removed = 0;
+    let mut preceding = vec![];
+    let mut kept = Vec::with_capacity(data.subs.len());
+    for mut sub in std::mem::take(&mut data.subs) {
+        let text: Vec<&str> = sub
+            .lines
+            .iter()
+            .map(|line| line.trim_end_matches(&['\r', '\n'][..]))
+            .filter(|line| !line.contains(&['♪', '♫'][..]))
+            .collect();
+        let mut text = DESCRIPTION.replace_all(&text.join("\n"), "").to_string();
+        text = SPEAKER.replace_all(&text, "$1").to_string();
+        for pattern in patterns {
+            text = pattern.replace_all(&text, "").to_string();
+        }
+
+        let mut lines: Vec<String> = text
+            .lines()
+            .map(|line| SPACES.replace_all(line, " ").trim().to_string())
+            .filter(|line| !plain_text(line).trim_start_matches('-').trim().is_empty())
+            .collect();
+        // A dialogue dash is only needed with two speakers:
+        if let [line] = lines.as_mut_slice() {
+            if let Some(rest) = line.strip_prefix('-') {
+                *line = rest.trim_start().to_string();
+            }
+        }
+
+        // Comments or styles before a removed subtitle go before the next one:
+        preceding.append(&mut sub.preceding);
+        if lines.is_empty() {
+            removed += 1;
+            continue;
+        }
+        sub.preceding = std::mem::take(&mut preceding);
+        sub.lines = lines
+            .into_iter()
+            .map(|line| format!("{}{}", line, line_ending))
+            .collect();
+        kept.push(sub);
+    }
+    preceding.append(&mut data.trailer);
+    data.trailer = preceding;
+    data.subs = kept;
+    info!(
+        "Removed the hearing impaired text, and {} subtitles that had nothing else.",
+        removed
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use regex::Regex;
+
+    use crate::{Format, SubData};
+
+    use super::strip;
+
+    #[test]
+    fn test_strip() {
+        let text = "1\n00:00:01,000 --> 00:00:02,000\n[door slams]\n\n\
+                    2\n00:00:03,000 --> 00:00:04,000\nJOHN: Who's there?\n\n\
+                    3\n00:00:05,000 --> 00:00:06,000\n- (gasps)\n- <i>MARY: It's me.</i>\n\n\
+                    4\n00:00:07,000 --> 00:00:08,000\n♪ La la la ♪\nI'm  [laughs] home.\n\n\
+                    5\n00:00:09,000 --> 00:00:10,000\n(MUSIC\nPLAYING)\n\n\
+                    6\n00:00:11,000 --> 00:00:12,000\nAt 10:30, Dr. Who: ok.\n\n\
+                    7\n00:00:13,000 --> 00:00:14,000\nSubtitles by XYZ\n";
+        let mut data = SubData::parse(text, Format::Srt, None).unwrap();
+        strip(&mut data, &[Regex::new("(?i)subtitles by.*").unwrap()]);
+        assert_eq!(
+            data.to_string(),
+            "2\n00:00:03,000 --> 00:00:04,000\nWho's there?\n\n\
+             3\n00:00:05,000 --> 00:00:06,000\n<i>It's me.</i>\n\n\
+             4\n00:00:07,000 --> 00:00:08,000\nI'm home.\n\n\
+             6\n00:00:11,000 --> 00:00:12,000\nAt 10:30, Dr. Who: ok.\n\n"
+        );
+    }
+}
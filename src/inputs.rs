@@ -0,0 +1,133 @@
+//! Finding the subtitle files to work on from the paths, glob patterns and directories given
+//! on the command line.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Result};
+use log::warn;
+
+use crate::{is_std_stream, Format};
+
+/// Turn the inputs into a list of files. Glob patterns like `season1/*.srt` are expanded, for
+/// shells that don't do it. Directories are searched for subtitle files if `recursive` is set.
+/// Files that will be changed in place can't be symlinks.
+pub fn expand(inputs: &[PathBuf], recursive: bool, in_place: bool) -> Result<Vec<PathBuf>> {
+    let mut files = vec![];
+    for input in inputs {
+        if is_std_stream(input) {
+            files.push(input.clone());
+        } else if input.exists() {
+            add(input, recursive, in_place, true, &mut files)?;
+        } else if is_pattern(input) {
+            let pattern = input.to_string_lossy();
+            let mut matched = false;
+            for path in glob::glob(&pattern)? {
+                matched = true;
+                add(&path?, recursive, in_place, false, &mut files)?;
+            }
+            if !matched {
+                bail!("No files match {:#?}", input);
+            }
+        } else {
+            bail!("Input path does not exist: {:#?}", input);
+        }
+    }
+
+    let mut seen = std::collections::HashSet::new();
+    files.retain(|file| seen.insert(file.clone()));
+    if files.is_empty() {
+        bail!("No subtitle files were found.");
+    }
+    Ok(files)
+}
+
+fn is_pattern(path: &Path) -> bool {
+    path.to_string_lossy().contains(&['*', '?', '['][..])
+}
+
+/// Add a file, or the subtitle files in a directory. Symlinks that weren't asked for by name
+/// are skipped.
+fn add(
+    path: &Path,
+    recursive: bool,
+    in_place: bool,
+    by_name: bool,
+    files: &mut Vec<PathBuf>,
+) -> Result<()> {
+    if in_place && std::fs::read_link(path).is_ok() {
+        // Note: we're not checking for special file types. That's rare and requires
+        // platform specific code.
+        if by_name {
+            bail!("Will not modify a symlink: {:#?}", path);
+        }
+        warn!("Skipping symlink {:#?}", path);
+    } else if path.is_dir() {
+        if !recursive {
+            if by_name {
+                bail!(
+                    "{:#?} is a directory. Use `--recursive` to work on the subtitles in it.",
+                    path
+                );
+            }
+            // Directories matched by a glob pattern are left out:
+            return Ok(());
+        }
+        let mut entries = std::fs::read_dir(path)?
+            .map(|entry| Ok(entry?.path()))
+            .collect::<Result<Vec<PathBuf>>>()?;
+        entries.sort();
+        for entry in entries {
+            if entry.is_dir() || Format::from_extension(&entry).is_some() {
+                add(&entry, recursive, in_place, false, files)?;
+            }
+        }
+    } else {
+        files.push(path.to_path_buf());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::PathBuf;
+
+    use super::expand;
+
+    #[test]
+    fn test_expand() {
+        let dir = std::env::temp_dir().join(format!("subtitle-adjust-test-{}", std::process::id()));
+        fs::create_dir_all(dir.join("season/extras")).unwrap();
+        for file in [
+            "season/e02.srt",
+            "season/e01.ass",
+            "season/notes.txt",
+            "season/extras/x.vtt",
+        ] {
+            fs::write(dir.join(file), "").unwrap();
+        }
+        let path = |file: &str| dir.join(file);
+
+        let files = expand(&[path("season")], true, true).unwrap();
+        assert_eq!(
+            files,
+            vec![
+                path("season/e01.ass"),
+                path("season/e02.srt"),
+                path("season/extras/x.vtt")
+            ]
+        );
+        assert!(expand(&[path("season")], false, true).is_err());
+
+        let files = expand(&[path("season/*.srt"), path("season/e0?.*")], false, true).unwrap();
+        assert_eq!(files, vec![path("season/e02.srt"), path("season/e01.ass")]);
+        assert!(expand(&[path("season/*.sub")], false, true).is_err());
+        assert!(expand(&[path("season/e03.srt")], false, true).is_err());
+        assert_eq!(
+            expand(&[PathBuf::from("-")], false, true).unwrap(),
+            vec![PathBuf::from("-")]
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -4,15 +4,41 @@ use std::{
 };
 
 use anyhow::{anyhow, bail, Result};
+use encoding::{parse_encoding, parse_input_encoding, TextEncoding};
+use encoding_rs::Encoding;
+use frame_rate::FrameRate;
 use lazy_static::lazy_static;
 use regex::Regex;
+use replace::{parse_replacement, Replacement};
 use structopt::*;
-
-const PAL: f64 = 25.0;
-const NTSC: f64 = 23.976;
+use sync::{parse_anchor, validate_anchors, Anchor};
+
+pub mod ass;
+pub mod audio;
+pub mod check;
+mod convert;
+pub mod dry_run;
+pub mod duration;
+pub mod encoding;
+pub mod frame_rate;
+pub mod hearing_impaired;
+pub mod inputs;
+pub mod microdvd;
+pub mod overlaps;
+pub mod reference;
+pub mod replace;
+pub mod speed;
+pub mod split_merge;
+mod srt;
+pub mod sync;
+pub mod tags;
+pub mod vtt;
+pub mod wrap;
 
 #[derive(Debug, StructOpt)]
-#[structopt(about = "Adjust subtitle timing or positions in SRT files.")]
+#[structopt(
+    about = "Adjust subtitle timing or positions in SRT, WebVTT, ASS/SSA or MicroDVD files."
+)]
 /// Use this program to fix the time offset or time scale of subtitles that were meant for a different
 /// cut or a different playback speed.
 ///
@@ -26,11 +52,36 @@ const NTSC: f64 = 23.976;
 /// timing changes.
 ///
 /// Times are input as [[hh:]mm:]ss[,ms], a decimal number of seconds, or a mix like 1:30.4.
-
+/// Offsets and their start times may also be given as a number of frames, like 120f.
 pub struct Opt {
-    /// Input file in the SubRip (.srt) format.
-    #[structopt(parse(from_os_str), name("input"))]
-    path: PathBuf,
+    /// Input file in the SubRip (.srt), WebVTT (.vtt), SubStation Alpha (.ass, .ssa) or
+    /// MicroDVD (.sub) format. Use - to read from standard input. Several files or glob
+    /// patterns like season1/*.srt can be given, and the same changes are made to each.
+    #[structopt(parse(from_os_str), name("input"), required(true))]
+    paths: Vec<PathBuf>,
+
+    /// Work on all subtitle files in the directories given as input, and in their subdirectories.
+    #[structopt(long)]
+    recursive: bool,
+
+    /// Write the result to this file instead of changing the input file, or use - to write to
+    /// standard output. If the file's extension is for another subtitle format, the subtitles
+    /// are converted to it. Input read from standard input is written to standard output unless
+    /// this is given.
+    #[structopt(short = "O", long, parse(from_os_str))]
+    output: Option<PathBuf>,
+
+    /// Don't write anything. Instead, show a table of the subtitles that would change, with
+    /// their times before and after, and the subtitles that would be removed or added.
+    #[structopt(long)]
+    dry_run: bool,
+
+    /// The video frame rate. MicroDVD files count time in frames, so this is needed for them
+    /// unless the file starts with a frame rate line like {1}{1}23.976. It's also needed for
+    /// times given in frames, like 120f, when the subtitle format doesn't use frames.
+    /// It can be a number, a fraction like 24000/1001, or film, ntsc or pal.
+    #[structopt(long)]
+    fps: Option<FrameRate>,
 
     #[structopt(flatten)]
     scale_opts: ScaleOpts,
@@ -38,6 +89,41 @@ pub struct Opt {
     #[structopt(flatten)]
     offset_opts: OffsetOpts,
 
+    /// Sync the subtitles with two pairs of times, like `--sync 10.5=12 --sync 1:45:00=1:46:30`.
+    /// Each pair is the time a line is shown in the subtitles and the time it's spoken in the video.
+    /// The offset and the scale are both worked out from the pairs, so they should be far apart.
+    /// With more than two pairs, each stretch between two pairs gets its own offset and scale,
+    /// which can fix subtitles for a cut with added or removed scenes.
+    #[structopt(long, parse(try_from_str = parse_anchor), allow_hyphen_values(true), number_of_values(1))]
+    sync: Vec<Anchor>,
+
+    /// Sync the subtitles to a reference subtitle file that is already in sync with the video,
+    /// for example one in another language. Lines are matched up by when they start and how long
+    /// they last, and the offset and scale are worked out from them.
+    #[structopt(long, parse(from_os_str))]
+    reference: Option<PathBuf>,
+
+    /// Sync the subtitles to the speech in the audio of the video. This can be a WAV file, or,
+    /// if ffmpeg or ffmpeg.exe is found, a video or any other audio file.
+    #[structopt(long, parse(from_os_str))]
+    audio: Option<PathBuf>,
+
+    /// Report whether the subtitles were timed for a video with a different frame rate, like
+    /// 25 fps PAL instead of 23.976 fps film, without changing anything. This needs `--reference`
+    /// or `--video-duration` to compare with.
+    #[structopt(long)]
+    detect_speed: bool,
+
+    /// The length of the video, for `--detect-speed` and `--fix-speed`. Because of the end
+    /// credits, this only narrows the speed down; `--reference` is more accurate.
+    #[structopt(long, parse(try_from_str = parse_ms))]
+    video_duration: Option<i64>,
+
+    /// With `--reference`, allow different parts of the subtitles to get different offsets,
+    /// for a cut with added or removed scenes.
+    #[structopt(long)]
+    piecewise: bool,
+
     /// Move subtitles in this time range to the top of the screen.
     /// This operation can't be used with subtitles that have pixel-based positions.
     /// The time given is before any timing adjustments.
@@ -55,6 +141,13 @@ pub struct Opt {
     #[structopt(long, parse(try_from_str = parse_timespan), allow_hyphen_values(true))]
     to_bottom: Vec<TimeSpan>,
 
+    /// Shift subtitles in a time range, like `--shift 10:00-20:00:2.5`. The offset comes after
+    /// the last colon. It's in seconds, or frames like 48f, and can be negative. This can be used
+    /// more than once to give different ranges different offsets.
+    /// The time range is before any timing adjustments, and its start or end may be omitted.
+    #[structopt(long, parse(try_from_str = parse_shift), allow_hyphen_values(true), number_of_values(1))]
+    shift: Vec<Shift>,
+
     /// Should the number of the subtitles be recounted/rewritten?
     #[structopt(short, long)]
     renumber: bool,
@@ -63,25 +156,146 @@ pub struct Opt {
     /// subtitle file format.
     #[structopt(short, long)]
     extract: bool,
+
+    /// Convert the subtitles to this format: srt, vtt, ass or microdvd. The result is written
+    /// next to the input file, with the extension of the new format. Anything that can't be
+    /// represented in the new format is reported.
+    #[structopt(long)]
+    output_format: Option<Format>,
+
+    /// Write the subtitles in this text encoding, like windows-1250, windows-1251, Shift_JIS or
+    /// UTF-16LE. By default, the encoding and byte order mark of the input are kept. UTF-16 is
+    /// always written with a byte order mark; use utf-8-bom for UTF-8 with one.
+    #[structopt(long, parse(try_from_str = parse_encoding))]
+    output_encoding: Option<TextEncoding>,
+
+    /// Read the subtitles in this text encoding, like windows-1251 or Big5. By default, the
+    /// encoding is detected, and a guessed one is reported with how sure the guess is.
+    #[structopt(long, parse(try_from_str = parse_input_encoding))]
+    input_encoding: Option<&'static Encoding>,
+
+    /// Refuse SubRip files with any mistakes in them, and list each one with its line and
+    /// column. By default, common mistakes like missing numbers or blank lines are worked
+    /// around with a warning.
+    #[structopt(long)]
+    strict: bool,
+
+    /// Report problems in the subtitles without changing them: times that are out of order,
+    /// overlap, are negative or have no duration, numbers that are repeated or out of order,
+    /// empty subtitles, lines longer than 42 characters, mixed line endings, and mistakes in
+    /// SubRip files. The exit code is 0 if nothing was found, 2 for warnings, and 3 for errors.
+    #[structopt(long)]
+    check: bool,
+
+    /// Print the `--check` report as JSON.
+    #[structopt(long)]
+    json: bool,
+
+    /// Sort the subtitles by start time, and end each one before the next one starts.
+    /// Subtitles that start at the same time are merged into one. Subtitles shown in different
+    /// places, like at the top and the bottom, are left alone. This is done after any other
+    /// time changes, and the subtitles are numbered again.
+    #[structopt(long)]
+    fix_overlaps: bool,
+
+    /// The time to leave between subtitles with `--fix-overlaps`, `--min-duration` and
+    /// `--max-cps`, like 0.08, or a number of frames like 2f. The default is no gap.
+    #[structopt(long, parse(try_from_str = parse_time))]
+    min_gap: Option<Time>,
+
+    /// Show each subtitle for at least this long, like 0.7, by ending it later. Subtitles are
+    /// never made to run into the next one; the ones that can't be fixed are reported.
+    #[structopt(long, parse(try_from_str = parse_time))]
+    min_duration: Option<Time>,
+
+    /// Show each subtitle for at most this long, like 7, by ending it sooner.
+    #[structopt(long, parse(try_from_str = parse_time))]
+    max_duration: Option<Time>,
+
+    /// The most characters per second viewers should need to read, like 17. Subtitles with
+    /// more text are shown for longer, like with `--min-duration`.
+    #[structopt(long)]
+    max_cps: Option<f64>,
+
+    /// Remove the text meant for deaf and hard of hearing viewers: sound descriptions in
+    /// brackets or parentheses, lines with music notes, and speaker labels like "JOHN:".
+    /// Subtitles with nothing else are removed; use `--renumber` to number the rest again.
+    #[structopt(long)]
+    strip_hi: bool,
+
+    /// A file of more patterns to remove with `--strip-hi`, one regular expression per line.
+    /// Blank lines and lines starting with # are skipped.
+    #[structopt(long, parse(from_os_str))]
+    hi_patterns: Option<PathBuf>,
+
+    /// Remove formatting tags from the text, like <i>, <b>, <u>, <font color="red">, and ASS
+    /// override blocks like {\an8\i1}, for players that show them as text. Subtitles that were
+    /// only tags are removed; use `--renumber` to number the rest again.
+    #[structopt(long)]
+    strip_tags: bool,
+
+    /// Keep italics with `--strip-tags`. Italic tags that aren't closed, or are empty, are fixed.
+    #[structopt(long)]
+    keep_italics: bool,
+
+    /// Keep position tags like {\an8} with `--strip-tags`.
+    #[structopt(long)]
+    keep_position: bool,
+
+    /// Reflow the text of each subtitle into one line, or two balanced lines, of at most this
+    /// many characters, like 42. Lines are broken after punctuation or before words like "and"
+    /// when possible. Chinese, Japanese and Korean characters count as two.
+    #[structopt(long, name = "MAX_CHARS")]
+    rewrap: Option<usize>,
+
+    /// Split subtitles with more than this many characters of text, like 84, into as few as
+    /// needed, preferably at the end of a sentence. The time is divided by the length of the
+    /// text of each part. Subtitles are numbered again.
+    #[structopt(long, name = "MAX_TEXT")]
+    split_long: Option<usize>,
+
+    /// Merge subtitles that follow each other into one, while the text of the merged one has
+    /// at most this many characters, like 42. Only subtitles shown in the same place are
+    /// merged. Subtitles are numbered again.
+    #[structopt(long, name = "MAX_MERGED")]
+    merge_short: Option<usize>,
+
+    /// The longest gap between subtitles that `--merge-short` merges, like 0.5, or a number of
+    /// frames like 12f. The default is 1 second.
+    #[structopt(long, parse(try_from_str = parse_time))]
+    merge_gap: Option<Time>,
+
+    /// Replace text matching a regular expression, like `colour=color`. The replacement can
+    /// use groups of the pattern, like `(\w+)ise=${1}ize`, and `(?i)` makes the pattern ignore
+    /// case. The pattern ends at the first `=`. A time range can be added after an `@`, like
+    /// `(?i)\bjon\b=John@10:00-20:00`. This can be used more than once. Subtitles with no text
+    /// left are removed.
+    #[structopt(long, name = "PATTERN=REPLACEMENT", parse(try_from_str = parse_replacement), allow_hyphen_values(true), number_of_values(1))]
+    replace: Vec<Replacement>,
+
+    /// A file of replacements like for `--replace`, one per line. Blank lines and lines
+    /// starting with # are skipped. These are made before the ones given with `--replace`.
+    #[structopt(long, parse(from_os_str))]
+    replace_file: Option<PathBuf>,
 }
 
 #[derive(Debug, StructOpt)]
 struct OffsetOpts {
     /// `--from` and `--to` can be used together to create an offset, instead of `--offset`.
-    #[structopt(short, long, parse(try_from_str = parse_ms), allow_hyphen_values(true))]
-    from: Option<i64>,
+    #[structopt(short, long, parse(try_from_str = parse_time), allow_hyphen_values(true))]
+    from: Option<Time>,
     /// `--from` and `--to` can be used together to create an offset, instead of `--offset`.
-    #[structopt(short, long, parse(try_from_str = parse_ms), allow_hyphen_values(true))]
-    to: Option<i64>,
+    #[structopt(short, long, parse(try_from_str = parse_time), allow_hyphen_values(true))]
+    to: Option<Time>,
 
     /// How much should the subtitle be shifted forward? Negative values will shift the subtitles backward.
-    #[structopt(short, long, parse(try_from_str = parse_ms), allow_hyphen_values(true))]
-    offset: Option<i64>,
+    #[structopt(short, long, parse(try_from_str = parse_time), allow_hyphen_values(true))]
+    offset: Option<Time>,
 
     /// At what timestamp should subtitles start to be adjusted? Adjustment will occur from this
     /// point to the end.
-    #[structopt(short = "s", long, parse(try_from_str = parse_ms), allow_hyphen_values(true))]
-    offset_start: Option<i64>,
+    #[structopt(short = "s", long, parse(try_from_str = parse_time), allow_hyphen_values(true))]
+    offset_start: Option<Time>,
 }
 
 #[derive(Debug, StructOpt)]
@@ -92,8 +306,8 @@ struct ScaleOpts {
 
     /// This is the time that's assumed to be perfectly matched already
     /// when scaling subtitles faster or slower.
-    #[structopt(long, parse(try_from_str = parse_ms), allow_hyphen_values(true))]
-    scale_pivot: Option<i64>,
+    #[structopt(long, parse(try_from_str = parse_time), allow_hyphen_values(true))]
+    scale_pivot: Option<Time>,
 
     /// If the subtitles are continually lagging more and more behind, use this option. It will guess
     /// the values for the most common scenario.
@@ -103,16 +317,57 @@ struct ScaleOpts {
     /// the values for the most common scenario.
     #[structopt(long)]
     subs_are_fast: bool,
+
+    /// The frame rate the subtitles were timed for, to be used with `--fps-to`. The scale is
+    /// worked out exactly from the two rates. Each can be a number like 23.976 or 29.97, a
+    /// fraction like 24000/1001, or film (24), ntsc (23.976) or pal (25).
+    /// `--subs-are-fast` is the same as `--fps-from pal --fps-to ntsc`.
+    #[structopt(long)]
+    fps_from: Option<FrameRate>,
+    /// The frame rate of the video the subtitles should fit, to be used with `--fps-from`.
+    #[structopt(long)]
+    fps_to: Option<FrameRate>,
+    /// Like `--detect-speed`, but also apply the speed change if it's a common one. The offset
+    /// may still need fixing afterwards.
+    #[structopt(long)]
+    fix_speed: bool,
 }
 
 impl Opt {
+    fn changes_durations(&self) -> bool {
+        self.min_duration.is_some() || self.max_duration.is_some() || self.max_cps.is_some()
+    }
+
+    fn replaces(&self) -> bool {
+        !self.replace.is_empty() || self.replace_file.is_some()
+    }
+
+    fn changes_cues(&self) -> bool {
+        self.split_long.is_some() || self.merge_short.is_some()
+    }
+
     pub fn validate(&mut self) -> Result<OptFinal> {
-        if !Path::exists(self.path.as_path()) {
-            bail!("Input path does not exist: {:#?}", self.path);
-        } else if std::fs::read_link(self.path.as_path()).is_ok() {
-            // Note: we're not checking for special file types. That's rare and requires
-            // platform specific code.
-            bail!("Will not modify a symlink.");
+        if self.extract && self.recursive {
+            bail!("`--extract` works on video files, so it can't be used with `--recursive`.");
+        }
+        // Only the options that write the subtitles back to the input file change it:
+        let in_place = self.output.is_none() && !self.check && !self.dry_run && !self.detect_speed;
+        let paths = inputs::expand(&self.paths, self.recursive, in_place)?;
+        if paths.len() > 1 {
+            if paths.iter().any(|path| is_std_stream(path)) {
+                bail!("Standard input can't be used with other input files.");
+            }
+            if self.output.is_some()
+                || self.reference.is_some()
+                || self.audio.is_some()
+                || self.detect_speed
+                || self.scale_opts.fix_speed
+            {
+                bail!("`--output`, `--reference`, `--audio`, `--detect-speed` and `--fix-speed` only work with one input file.");
+            }
+        }
+        if self.extract && paths.iter().any(|path| is_std_stream(path)) {
+            bail!("`--extract` needs an input file, not standard input.");
         }
 
         if self.offset_opts.from.is_some() != self.offset_opts.to.is_some() {
@@ -121,22 +376,136 @@ impl Opt {
         if self.offset_opts.from.is_some() && self.offset_opts.offset.is_some() {
             bail!("The `--from`/`--to` arguments can't be uset with `--offset`.")
         }
+        if self.scale_opts.fps_from.is_some() != self.scale_opts.fps_to.is_some() {
+            bail!("The `--fps-from` and `--fps-to` arguments must be used together.")
+        }
         if self.scale_opts.subs_are_fast as i32
             + self.scale_opts.subs_are_slow as i32
             + self.scale_opts.scale.is_some() as i32
+            + self.scale_opts.fps_from.is_some() as i32
+            + self.scale_opts.fix_speed as i32
+            + self.detect_speed as i32
             + self.extract as i32
             > 1
         {
             bail!(
-                "Only one of the --extract, --scale, --subs-are-fast, and --subs-are-slow options are allowed."
+                "Only one of the --extract, --scale, --subs-are-fast, --subs-are-slow, --fps-from, --detect-speed and --fix-speed options are allowed."
             )
         }
 
-        // Convert from subs are fast/slow to scale
+        let find_speed = self.detect_speed || self.scale_opts.fix_speed;
+        if find_speed && self.reference.is_some() == self.video_duration.is_some() {
+            bail!("`--detect-speed` and `--fix-speed` need one of `--reference` or `--video-duration`.");
+        }
+        if !find_speed && self.video_duration.is_some() {
+            bail!("`--video-duration` can only be used with `--detect-speed` or `--fix-speed`.");
+        }
+        if find_speed
+            && (self.offset_opts.offset.is_some()
+                || self.offset_opts.from.is_some()
+                || self.offset_opts.offset_start.is_some()
+                || !self.sync.is_empty()
+                || !self.shift.is_empty()
+                || self.audio.is_some()
+                || self.piecewise)
+        {
+            bail!("`--detect-speed` and `--fix-speed` can't be used with the offset or sync options. Fix the speed first.");
+        }
+        if self.detect_speed
+            && (self.scale_opts.scale_pivot.is_some()
+                || self.renumber
+                || !self.to_top.is_empty()
+                || !self.to_bottom.is_empty()
+                || self.output_format.is_some()
+                || self.output.is_some()
+                || self.output_encoding.is_some()
+                || self.dry_run
+                || self.fix_overlaps
+                || self.changes_durations()
+                || self.strip_hi
+                || self.strip_tags
+                || self.rewrap.is_some()
+                || self.changes_cues()
+                || self.replaces())
+        {
+            bail!("`--detect-speed` only reports the speed, so it can't be used with other operations.");
+        }
+        if self.check
+            && (self.offset_opts.offset.is_some()
+                || self.offset_opts.from.is_some()
+                || self.offset_opts.offset_start.is_some()
+                || self.scale_opts.scale.is_some()
+                || self.scale_opts.scale_pivot.is_some()
+                || self.scale_opts.subs_are_fast
+                || self.scale_opts.subs_are_slow
+                || self.scale_opts.fps_from.is_some()
+                || self.scale_opts.fix_speed
+                || self.detect_speed
+                || self.extract
+                || !self.sync.is_empty()
+                || !self.shift.is_empty()
+                || self.reference.is_some()
+                || self.audio.is_some()
+                || self.renumber
+                || !self.to_top.is_empty()
+                || !self.to_bottom.is_empty()
+                || self.output_format.is_some()
+                || self.output.is_some()
+                || self.output_encoding.is_some()
+                || self.dry_run
+                || self.strict
+                || self.fix_overlaps
+                || self.changes_durations()
+                || self.strip_hi
+                || self.strip_tags
+                || self.rewrap.is_some()
+                || self.changes_cues()
+                || self.replaces())
+        {
+            bail!("`--check` only reports problems, so it can't be used with other operations.");
+        }
+        if self.json && !self.check {
+            bail!("`--json` can only be used with `--check`.");
+        }
+        if self.min_gap.is_some() && !self.fix_overlaps && !self.changes_durations() {
+            bail!("`--min-gap` can only be used with `--fix-overlaps`, `--min-duration` or `--max-cps`.");
+        }
+        if self.rewrap == Some(0) {
+            bail!("`--rewrap` needs at least one character per line.");
+        }
+        if self.split_long == Some(0) || self.merge_short == Some(0) {
+            bail!("`--split-long` and `--merge-short` need at least one character of text.");
+        }
+        if self.merge_gap.is_some() && self.merge_short.is_none() {
+            bail!("`--merge-gap` can only be used with `--merge-short`.");
+        }
+        if (self.keep_italics || self.keep_position) && !self.strip_tags {
+            bail!("`--keep-italics` and `--keep-position` can only be used with `--strip-tags`.");
+        }
+        if self.hi_patterns.is_some() && !self.strip_hi {
+            bail!("`--hi-patterns` can only be used with `--strip-hi`.");
+        }
+        if self.max_cps.is_some_and(|cps| cps.is_nan() || cps <= 0.0) {
+            bail!("`--max-cps` must be a positive number.");
+        }
+        if [self.min_duration, self.max_duration]
+            .iter()
+            .any(|time| matches!(time, Some(Time::Ms(n) | Time::Frames(n)) if *n <= 0))
+        {
+            bail!("`--min-duration` and `--max-duration` must be longer than zero.");
+        }
+
+        // Convert from subs are fast/slow and frame rates to scale
         if self.scale_opts.subs_are_fast {
-            self.scale_opts.scale.replace(PAL / NTSC);
+            self.scale_opts
+                .scale
+                .replace(FrameRate::PAL.ratio(FrameRate::NTSC));
         } else if self.scale_opts.subs_are_slow {
-            self.scale_opts.scale.replace(NTSC / PAL);
+            self.scale_opts
+                .scale
+                .replace(FrameRate::NTSC.ratio(FrameRate::PAL));
+        } else if let (Some(from), Some(to)) = (self.scale_opts.fps_from, self.scale_opts.fps_to) {
+            self.scale_opts.scale.replace(from.ratio(to));
         }
 
         if self.offset_opts.offset_start.is_some() && self.scale_opts.scale.is_some() {
@@ -147,28 +516,108 @@ impl Opt {
         if self.offset_opts.offset.is_some() && self.scale_opts.scale.is_some() {
             // If this turns out to be useful, I'll add the feature.
             bail!("Cannot both scale and offset together, because mistakes are too likely. \
-                Instead, first sync the subtitles at a point in time then use --scale and --scale-pivot together.");
+                Instead, use --sync with two pairs of times, or first sync the subtitles at a point in time then use \
+                --scale and --scale-pivot together.");
+        }
+
+        if !self.shift.is_empty()
+            && (self.offset_opts.offset.is_some()
+                || self.offset_opts.from.is_some()
+                || self.offset_opts.offset_start.is_some()
+                || self.scale_opts.scale.is_some()
+                || !self.sync.is_empty())
+        {
+            bail!("`--shift` can't be used with the other offset, scale or sync options.");
+        }
+        for (i, first) in self.shift.iter().enumerate() {
+            for second in &self.shift[i + 1..] {
+                if first.range.contains(second.range.start_ms)
+                    || first.range.contains(second.range.end_ms)
+                    || second.range.contains(first.range.start_ms)
+                    || second.range.contains(first.range.end_ms)
+                {
+                    bail!(
+                        "The `--shift` time ranges overlap, so it's not clear which offset to use."
+                    );
+                }
+            }
+        }
+
+        if !self.sync.is_empty() {
+            self.sync.sort_by_key(|anchor| anchor.from_ms);
+            validate_anchors(&self.sync)?;
+            if self.offset_opts.offset.is_some()
+                || self.offset_opts.from.is_some()
+                || self.offset_opts.offset_start.is_some()
+                || self.scale_opts.scale.is_some()
+                || self.scale_opts.scale_pivot.is_some()
+            {
+                bail!("`--sync` works out the offset and scale itself, so it can't be used with the other offset or scale options.");
+            }
         }
 
-        if self.scale_opts.scale_pivot.is_some() && self.scale_opts.scale.is_none() {
+        if self.reference.is_some() && self.audio.is_some() {
+            bail!("Only one of `--reference` and `--audio` can be used.");
+        }
+        if (self.reference.is_some() || self.audio.is_some())
+            && (self.offset_opts.offset.is_some()
+                || self.offset_opts.from.is_some()
+                || self.offset_opts.offset_start.is_some()
+                || self.scale_opts.scale.is_some()
+                || (self.scale_opts.scale_pivot.is_some() && !self.scale_opts.fix_speed)
+                || !self.sync.is_empty()
+                || !self.shift.is_empty())
+        {
+            bail!("`--reference` and `--audio` work out the offset and scale themselves, so they can't be used with the other offset, scale or sync options.");
+        }
+        if self.piecewise && self.reference.is_none() {
+            bail!("`--piecewise` can only be used with `--reference`.");
+        }
+
+        if self.scale_opts.scale_pivot.is_some()
+            && self.scale_opts.scale.is_none()
+            && !self.scale_opts.fix_speed
+        {
             bail!("Cannot use a scale pivot without some type of time scaling.");
         }
 
         // Convert --to/--from to --offset:
         if self.offset_opts.from.is_some() {
-            self.offset_opts.offset =
-                Some(self.offset_opts.to.take().unwrap() - self.offset_opts.from.take().unwrap());
+            let from = self.offset_opts.from.take().unwrap();
+            let to = self.offset_opts.to.take().unwrap();
+            self.offset_opts.offset = Some(match (from, to) {
+                (Time::Ms(from), Time::Ms(to)) => Time::Ms(to - from),
+                (Time::Frames(from), Time::Frames(to)) => Time::Frames(to - from),
+                _ => bail!("`--from` and `--to` must both be times or both be frame numbers."),
+            });
         }
 
         if self.offset_opts.offset.is_none()
             && self.scale_opts.scale.is_none()
             && self.to_bottom.is_empty()
             && self.to_top.is_empty()
+            && self.output_format.is_none()
+            && self.sync.is_empty()
+            && self.shift.is_empty()
+            && self.reference.is_none()
+            && self.audio.is_none()
+            && !self.detect_speed
+            && !self.scale_opts.fix_speed
+            && self.output.is_none()
+            && self.output_encoding.is_none()
             && !self.extract
+            && !self.check
+            && !self.fix_overlaps
+            && !self.changes_durations()
+            && !self.strip_hi
+            && !self.strip_tags
+            && self.rewrap.is_none()
+            && !self.changes_cues()
+            && !self.replaces()
         {
             bail!(
-                "`--extract` or one of the offset options, the scale options, or the `--to-top`, `--to-bottom` \
-                options much be used.\nSee `--help` for details."
+                "`--check`, `--extract`, `--output-format`, `--sync`, `--reference`, `--audio`, `--shift`, `--detect-speed`, `--fix-speed`, `--fix-overlaps`, `--min-duration`, `--max-duration`, `--max-cps`, `--strip-hi`, `--strip-tags`, `--rewrap`, `--split-long`, `--merge-short`, `--replace`, `--replace-file`, `--output`, `--output-encoding` or one of the offset options, the scale options, \
+                or the `--to-top`, `--to-bottom` options much be used.\nSee `--help` for details."
             );
         }
 
@@ -192,40 +641,161 @@ impl Opt {
                 || self.offset_opts.offset.is_some()
                 || self.offset_opts.offset_start.is_some()
                 || !self.to_bottom.is_empty()
-                || !self.to_top.is_empty())
+                || !self.to_top.is_empty()
+                || !self.sync.is_empty()
+                || !self.shift.is_empty()
+                || self.reference.is_some()
+                || self.audio.is_some()
+                || self.output.is_some()
+                || self.dry_run
+                || self.output_format.is_some()
+                || self.output_encoding.is_some()
+                || self.input_encoding.is_some()
+                || self.strict
+                || self.fix_overlaps
+                || self.changes_durations()
+                || self.strip_hi
+                || self.strip_tags
+                || self.rewrap.is_some()
+                || self.changes_cues()
+                || self.replaces())
         {
             bail!("Cannot combine `--extract` with other options or operations.");
         }
 
+        let hi_patterns = match &self.hi_patterns {
+            Some(path) => hearing_impaired::read_patterns(path)?,
+            None => vec![],
+        };
+        let mut replacements = match &self.replace_file {
+            Some(path) => replace::read_replacements(path)?,
+            None => vec![],
+        };
+        replacements.extend(self.replace.iter().cloned());
+
         Ok(OptFinal {
-            path: self.path.clone(),
+            paths,
+            output: self.output.clone(),
+            dry_run: self.dry_run,
 
             scale: self.scale_opts.scale,
+            fps: self.fps.map(FrameRate::as_f64),
             scale_pivot: self.scale_opts.scale_pivot,
-            offset_ms: self.offset_opts.offset.unwrap_or_default(),
-            offset_start_ms: self.offset_opts.offset_start.unwrap_or(i64::MIN),
-            renumber_offset: self.renumber,
+            offset: self.offset_opts.offset.unwrap_or(Time::Ms(0)),
+            offset_start: self.offset_opts.offset_start,
+            renumber_offset: self.renumber || self.fix_overlaps || self.changes_cues(),
             to_top: self.to_top.clone(),
             to_bottom: self.to_bottom.clone(),
             extract: self.extract,
+            output_format: self.output_format,
+            output_encoding: self.output_encoding,
+            input_encoding: self.input_encoding,
+            strict: self.strict,
+            check: self.check,
+            json: self.json,
+            fix_overlaps: self.fix_overlaps,
+            min_gap: self.min_gap.unwrap_or(Time::Ms(0)),
+            min_duration: self.min_duration,
+            max_duration: self.max_duration,
+            max_cps: self.max_cps,
+            strip_hi: self.strip_hi,
+            hi_patterns,
+            rewrap: self.rewrap,
+            split_long: self.split_long,
+            merge_short: self.merge_short,
+            merge_gap: self.merge_gap.unwrap_or(Time::Ms(1000)),
+            replacements,
+            strip_tags: self.strip_tags.then_some(tags::Keep {
+                italics: self.keep_italics,
+                position: self.keep_position,
+            }),
+            sync: self.sync.clone(),
+            shift: self.shift.clone(),
+            reference: self.reference.clone(),
+            audio: self.audio.clone(),
+            detect_speed: self.detect_speed,
+            fix_speed: self.scale_opts.fix_speed,
+            video_duration: self.video_duration,
+            piecewise: self.piecewise,
         })
     }
 }
 
 /// This is a non-ambiguous version of the program options.
+#[derive(Clone)]
 pub struct OptFinal {
     pub scale: Option<f64>,
-    pub scale_pivot: Option<i64>,
-    pub offset_ms: i64,
-    pub offset_start_ms: i64,
+    pub fps: Option<f64>,
+    pub scale_pivot: Option<Time>,
+    pub offset: Time,
+    pub offset_start: Option<Time>,
     pub renumber_offset: bool,
-    pub path: PathBuf,
+    pub paths: Vec<PathBuf>,
+    pub output: Option<PathBuf>,
+    pub dry_run: bool,
     pub to_top: Vec<TimeSpan>,
     pub to_bottom: Vec<TimeSpan>,
     pub extract: bool,
+    pub output_format: Option<Format>,
+    pub output_encoding: Option<TextEncoding>,
+    pub input_encoding: Option<&'static Encoding>,
+    pub strict: bool,
+    pub check: bool,
+    pub json: bool,
+    pub fix_overlaps: bool,
+    pub min_gap: Time,
+    pub min_duration: Option<Time>,
+    pub max_duration: Option<Time>,
+    pub max_cps: Option<f64>,
+    pub strip_hi: bool,
+    pub hi_patterns: Vec<Regex>,
+    /// The tags to keep, if tags are being stripped.
+    pub strip_tags: Option<tags::Keep>,
+    pub rewrap: Option<usize>,
+    pub split_long: Option<usize>,
+    pub merge_short: Option<usize>,
+    pub merge_gap: Time,
+    pub replacements: Vec<Replacement>,
+    pub sync: Vec<Anchor>,
+    pub shift: Vec<Shift>,
+    pub reference: Option<PathBuf>,
+    pub audio: Option<PathBuf>,
+    pub detect_speed: bool,
+    pub fix_speed: bool,
+    pub video_duration: Option<i64>,
+    pub piecewise: bool,
 }
 
+/// A time from the command line, which may be given as a number of video frames.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Time {
+    Ms(i64),
+    Frames(i64),
+}
+
+impl Time {
+    /// Get the time in milliseconds. A frame rate is needed if the time is in frames.
+    pub fn to_ms(self, fps: Option<f64>) -> Result<i64> {
+        match (self, fps) {
+            (Time::Ms(ms), _) => Ok(ms),
+            (Time::Frames(frames), Some(fps)) => Ok(microdvd::frames_to_ms(frames, fps)),
+            (Time::Frames(_), None) => {
+                bail!(
+                    "A frame rate is needed to use times given in frames. Use `--fps` to set one."
+                )
+            }
+        }
+    }
+}
+
+/// An offset for the subtitles that start in a time range.
 #[derive(Debug, PartialEq, Clone)]
+pub struct Shift {
+    pub range: TimeSpan,
+    pub offset: Time,
+}
+
+#[derive(Debug, PartialEq, Clone, Default)]
 pub struct TimeSpan {
     pub start_ms: i64,
     pub end_ms: i64,
@@ -242,16 +812,30 @@ impl TimeSpan {
     }
 }
 
+#[derive(Default, Clone)]
 pub struct Subtitle {
     pub number: i64,
     pub time_span: TimeSpan,
     pub position: Option<Position>,
     pub lines: Vec<String>,
+    /// WebVTT cue identifier, which doesn't have to be a number.
+    pub identifier: Option<String>,
+    /// WebVTT cue settings, like `line:0 position:20%`.
+    pub settings: Option<String>,
+    /// The fields of an ASS `Dialogue:` line, in the order of the `Format:` line, without the text.
+    pub ass_fields: Vec<String>,
+    /// Text that is written unchanged before this subtitle, like WebVTT comment blocks or
+    /// ASS `Comment:` lines.
+    pub preceding: Vec<String>,
+    /// Where the subtitle was in the file it was read from, so it can be matched up after
+    /// subtitles are removed, split or sorted. Subtitles that were added have none.
+    pub index: Option<usize>,
 }
 
 /// Data of hard coded pixel-based positions. This format may be dependent on resolution.
 /// It's not well documented. Tags like {\an2}, {\an8} work better, but those are stored
 /// in the text data.
+#[derive(PartialEq, Clone)]
 pub struct Position {
     pub x1: i32, // position left
     pub x2: i32, // position right
@@ -259,7 +843,7 @@ pub struct Position {
     pub y2: i32, // position down
 }
 
-pub(crate) struct Milliseconds(pub i64);
+pub struct Milliseconds(pub i64);
 
 impl Display for Milliseconds {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -270,8 +854,8 @@ impl Display for Milliseconds {
             self.0
         };
 
-        let hours = ms / 3600_000;
-        ms -= hours * 3600_000;
+        let hours = ms / 3_600_000;
+        ms -= hours * 3_600_000;
         write!(f, "{:02}:", hours)?;
         let minutes = ms / 60_000;
         ms -= minutes * 60_000;
@@ -306,26 +890,130 @@ impl Display for Position {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Format {
+    #[default]
+    Srt,
+    Vtt,
+    /// Advanced SubStation Alpha, which also covers the older SubStation Alpha.
+    Ass,
+    /// MicroDVD, which times subtitles by frame number.
+    MicroDvd,
+}
+
+impl Format {
+    /// Guess the format from the file's signature, then from its extension.
+    pub fn detect(path: &Path, text: &str) -> Format {
+        if text.starts_with("WEBVTT") {
+            return Format::Vtt;
+        } else if text.trim_start().starts_with("[Script Info]") {
+            return Format::Ass;
+        } else if text.lines().next().is_some_and(microdvd::is_cue) {
+            return Format::MicroDvd;
+        }
+        Format::from_extension(path).unwrap_or_default()
+    }
+
+    /// The format that a file extension is for, if any.
+    pub fn from_extension(path: &Path) -> Option<Format> {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some(ext) if ext.eq_ignore_ascii_case("srt") => Some(Format::Srt),
+            Some(ext) if ext.eq_ignore_ascii_case("vtt") => Some(Format::Vtt),
+            Some(ext) if ext.eq_ignore_ascii_case("ass") || ext.eq_ignore_ascii_case("ssa") => {
+                Some(Format::Ass)
+            }
+            Some(ext) if ext.eq_ignore_ascii_case("sub") => Some(Format::MicroDvd),
+            _ => None,
+        }
+    }
+
+    /// The usual file extension of the format.
+    pub fn extension(self) -> &'static str {
+        match self {
+            Format::Srt => "srt",
+            Format::Vtt => "vtt",
+            Format::Ass => "ass",
+            Format::MicroDvd => "sub",
+        }
+    }
+}
+
+impl std::str::FromStr for Format {
+    type Err = anyhow::Error;
+
+    fn from_str(name: &str) -> Result<Self> {
+        Ok(match name.to_ascii_lowercase().as_str() {
+            "srt" | "subrip" => Format::Srt,
+            "vtt" | "webvtt" => Format::Vtt,
+            "ass" | "ssa" => Format::Ass,
+            "sub" | "microdvd" => Format::MicroDvd,
+            _ => bail!(
+                "Unknown subtitle format: {:#?}. Use srt, vtt, ass or microdvd.",
+                name
+            ),
+        })
+    }
+}
+
+#[derive(Default)]
 pub struct SubData {
     pub subs: Vec<Subtitle>,
     pub line_ending: String,
+    pub format: Format,
+    /// Lines before the first subtitle, like the WebVTT header or the ASS script info and styles.
+    pub header: Vec<String>,
+    /// Text after the last subtitle, like trailing WebVTT comments or ASS sections.
+    pub trailer: Vec<String>,
+    /// The frame rate that frame numbers in the file were converted with.
+    pub fps: Option<f64>,
+    /// The text encoding to write the file in.
+    pub encoding: TextEncoding,
+    /// Problems that were worked around while reading the file.
+    pub problems: Vec<ParseProblem>,
+}
+
+/// Something wrong in a subtitle file, at a line and column counted from 1.
+#[derive(Debug, PartialEq, Clone)]
+pub struct ParseProblem {
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl Display for ParseProblem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "line {}, column {}: {}",
+            self.line, self.column, self.message
+        )
+    }
+}
+
+impl SubData {
+    /// Parse subtitles in the given format. The frame rate is only used for frame based formats.
+    pub fn parse(text: &str, format: Format, fps: Option<f64>) -> Result<SubData> {
+        let mut data = match format {
+            Format::Srt => srt::parse(text),
+            Format::Vtt => vtt::parse(text),
+            Format::Ass => ass::parse(text),
+            Format::MicroDvd => microdvd::parse(text, fps),
+        }?;
+        for (i, sub) in data.subs.iter_mut().enumerate() {
+            sub.index = Some(i);
+        }
+        Ok(data)
+    }
 }
 
 impl Display for SubData {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        for sub in self.subs.iter() {
-            write!(f, "{}{}", sub.number, self.line_ending)?; // add the number
-            write!(f, "{}", sub.time_span)?; // add the times
-            if let Some(ref position) = sub.position {
-                write!(f, "  {}", position)?;
-            }
-            write!(f, "{}", self.line_ending)?;
-            for line in sub.lines.iter() {
-                write!(f, "{}", line)?; // add the text
-            }
-            f.write_str(self.line_ending.as_str())?; // add a blank line
+        match self.format {
+            Format::Srt => srt::write(self, f),
+            Format::Vtt => vtt::write(self, f),
+            Format::Ass => ass::write(self, f),
+            Format::MicroDvd => microdvd::write(self, f),
         }
-        Ok(())
     }
 }
 
@@ -339,6 +1027,11 @@ pub(crate) static NUMBER_REGEX: &str = r"(?x) # allow whitespace/comments
     \.(\d+) # only decimal, no prior digit
     )";
 
+/// Is this path `-`, meaning standard input or output?
+pub fn is_std_stream(path: &Path) -> bool {
+    path.as_os_str() == "-"
+}
+
 /// Parse the digits after "." to a decimal, so for example "050" ("0.05") is 50 ms.
 pub(crate) fn parse_decimal_part(n: &str) -> Result<u64> {
     let trimmed = n.trim_matches('0');
@@ -390,14 +1083,30 @@ pub fn parse_ms(input: &str) -> Result<i64> {
     }
 }
 
-/// Parse intervals like a-b, a-, -b, where a and b are timestamps.
-pub(crate) fn parse_timespan(input: &str) -> Result<TimeSpan> {
-    lazy_static! {
-        static ref RE: Regex =
-            Regex::new(format!(r"^({})?-({})?$", NUMBER_REGEX, NUMBER_REGEX).as_str()).unwrap();
+/// Parse a timestamp like `parse_ms`, or a number of frames like "120f".
+pub fn parse_time(input: &str) -> Result<Time> {
+    match input.trim().strip_suffix('f') {
+        Some(frames) => Ok(Time::Frames(frames.parse().map_err(|_| {
+            anyhow!("Cannot coerce value into a number of frames: {}", input)
+        })?)),
+        None => Ok(Time::Ms(parse_ms(input)?)),
     }
+}
+
+lazy_static! {
+    static ref TIMESPAN_REGEX: Regex =
+        Regex::new(format!(r"^({})?-({})?$", NUMBER_REGEX, NUMBER_REGEX).as_str()).unwrap();
+}
 
-    let captures = RE
+/// Is this written like a time range, which `parse_timespan` can parse if the end comes after
+/// the start?
+pub(crate) fn is_timespan(input: &str) -> bool {
+    TIMESPAN_REGEX.is_match(input)
+}
+
+/// Parse intervals like a-b, a-, -b, where a and b are timestamps.
+pub(crate) fn parse_timespan(input: &str) -> Result<TimeSpan> {
+    let captures = TIMESPAN_REGEX
         .captures(input)
         .ok_or_else(|| anyhow!("Malformed timespan: {:#?}", input))?;
 
@@ -414,15 +1123,110 @@ pub(crate) fn parse_timespan(input: &str) -> Result<TimeSpan> {
     Ok(TimeSpan::new(start_time, end_time))
 }
 
+/// Parse a time range and an offset separated by a colon, like 10-20:-1.5.
+pub(crate) fn parse_shift(input: &str) -> Result<Shift> {
+    let (range, offset) = input
+        .rsplit_once(':')
+        .ok_or_else(|| anyhow!("Expecting time range:offset, got: {:#?}", input))?;
+    Ok(Shift {
+        range: parse_timespan(range)?,
+        offset: parse_time(offset)?,
+    })
+}
+
+/// Find pairs of subtitles that run into each other because of a change: they overlap or
+/// swap order when they didn't before. The spans before and after the change must be in the
+/// same order, and pairs are returned as indices.
+pub fn new_collisions(before: &[TimeSpan], after: &[TimeSpan]) -> Vec<(usize, usize)> {
+    let overlap = |a: &TimeSpan, b: &TimeSpan| a.start_ms < b.end_ms && b.start_ms < a.end_ms;
+    let mut collisions = vec![];
+    for i in 0..after.len() {
+        for j in i + 1..after.len() {
+            let swapped = (before[i].start_ms <= before[j].start_ms)
+                != (after[i].start_ms <= after[j].start_ms);
+            if swapped || (overlap(&after[i], &after[j]) && !overlap(&before[i], &before[j])) {
+                collisions.push((i, j));
+            }
+        }
+    }
+    collisions
+}
+
+/// Subtitles for the tests of several modules.
+#[cfg(test)]
+pub(crate) mod test_subs {
+    use crate::{Subtitle, TimeSpan};
+
+    /// A subtitle with some text, where `\n` separates the lines.
+    pub(crate) fn sub(number: i64, start_ms: i64, end_ms: i64, text: &str) -> Subtitle {
+        Subtitle {
+            number,
+            time_span: TimeSpan::new(start_ms, end_ms),
+            lines: text.split('\n').map(|line| format!("{}\n", line)).collect(),
+            ..Default::default()
+        }
+    }
+
+    /// Subtitles with irregular lengths and gaps, like real dialogue.
+    pub(crate) fn dialogue(count: i64, start_ms: i64, min_gap_ms: i64) -> Vec<Subtitle> {
+        let mut start = start_ms;
+        (0..count)
+            .map(|i| {
+                let duration = 800 + (i * 7919 % 23) * 150;
+                let gap = min_gap_ms + (i * 104_729 % 31) * 170;
+                let sub = Subtitle {
+                    number: i + 1,
+                    time_span: TimeSpan::new(start, start + duration),
+                    ..Default::default()
+                };
+                start += duration + gap;
+                sub
+            })
+            .collect()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use regex::Regex;
 
+    use std::ffi::OsStr;
+    use std::path::Path;
+
+    use structopt::StructOpt;
+
     use crate::{
-        parse_decimal_part, parse_ms, parse_timespan, Milliseconds, Position, SubData, Subtitle,
+        is_std_stream, new_collisions, parse_decimal_part, parse_ms, parse_shift, parse_time,
+        parse_timespan, Format, Milliseconds, Opt, Position, Shift, SubData, Subtitle, Time,
         TimeSpan, NUMBER_REGEX,
     };
 
+    #[test]
+    #[cfg(unix)]
+    fn test_validate_symlink() {
+        let dir = std::env::temp_dir().join(format!(
+            "subtitle-adjust-symlink-test-{}",
+            std::process::id()
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("movie.srt"), "").unwrap();
+        let link = dir.join("link.srt");
+        std::os::unix::fs::symlink(dir.join("movie.srt"), &link).unwrap();
+        let validate = |options: &[&str]| {
+            let mut args: Vec<&OsStr> = vec![OsStr::new("subtitle-adjust")];
+            args.extend(options.iter().map(OsStr::new));
+            args.push(link.as_os_str());
+            Opt::from_iter_safe(args).unwrap().validate()
+        };
+
+        // Symlinks can be read, but aren't changed in place:
+        assert!(validate(&["--check"]).is_ok());
+        assert!(validate(&["--dry-run", "--strip-hi"]).is_ok());
+        assert!(validate(&["--strip-hi"]).is_err());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
     #[test]
     fn test_parse_decimal_part() {
         assert_eq!(parse_decimal_part("111").unwrap(), 111);
@@ -438,6 +1242,7 @@ mod tests {
     }
 
     #[test]
+    #[allow(clippy::identity_op)]
     fn test_parse_ms() {
         let re = Regex::new(format!("^{}$", NUMBER_REGEX).as_str()).unwrap();
         assert!(re.find("-").is_none());
@@ -473,6 +1278,19 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_parse_time() {
+        assert_eq!(parse_time("1:30").unwrap(), Time::Ms(90000));
+        assert_eq!(parse_time("120f").unwrap(), Time::Frames(120));
+        assert_eq!(parse_time("-48f").unwrap(), Time::Frames(-48));
+        assert!(parse_time("1.5f").is_err());
+        assert!(parse_time("f").is_err());
+
+        assert_eq!(Time::Frames(50).to_ms(Some(25.0)).unwrap(), 2000);
+        assert_eq!(Time::Ms(50).to_ms(None).unwrap(), 50);
+        assert!(Time::Frames(50).to_ms(None).is_err());
+    }
+
     #[test]
     pub fn test_parse_timespan() {
         assert_eq!(
@@ -504,6 +1322,62 @@ mod tests {
         assert!(parse_timespan("2-1").is_err());
     }
 
+    #[test]
+    fn test_parse_shift() {
+        assert_eq!(
+            parse_shift("10:00-20:00:2.5").unwrap(),
+            Shift {
+                range: TimeSpan::new(600_000, 1_200_000),
+                offset: Time::Ms(2500)
+            }
+        );
+        assert_eq!(
+            parse_shift("30-:-1").unwrap(),
+            Shift {
+                range: TimeSpan::new(30_000, i64::MAX),
+                offset: Time::Ms(-1000)
+            }
+        );
+        assert_eq!(
+            parse_shift("-:48f").unwrap(),
+            Shift {
+                range: TimeSpan::new(i64::MIN, i64::MAX),
+                offset: Time::Frames(48)
+            }
+        );
+        assert!(parse_shift("10-20").is_err());
+        assert!(parse_shift("20-10:1").is_err());
+    }
+
+    #[test]
+    fn test_new_collisions() {
+        let before = [
+            TimeSpan::new(0, 1000),
+            TimeSpan::new(500, 1500),
+            TimeSpan::new(2000, 3000),
+            TimeSpan::new(4000, 5000),
+        ];
+        assert!(new_collisions(&before, &before).is_empty());
+
+        // The first two overlapped before, so only the newly overlapping pair is reported:
+        let after = [
+            TimeSpan::new(0, 1000),
+            TimeSpan::new(500, 1500),
+            TimeSpan::new(4500, 5500),
+            TimeSpan::new(4000, 5000),
+        ];
+        assert_eq!(new_collisions(&before, &after), vec![(2, 3)]);
+
+        // A subtitle moved past others:
+        let after = [
+            TimeSpan::new(3200, 3500),
+            TimeSpan::new(500, 1500),
+            TimeSpan::new(2000, 3000),
+            TimeSpan::new(4000, 5000),
+        ];
+        assert_eq!(new_collisions(&before, &after), vec![(0, 1), (0, 2)]);
+    }
+
     #[test]
     fn test_format_ms() {
         assert_eq!(format!("{}", Milliseconds(65565123)), "18:12:45,123");
@@ -517,6 +1391,7 @@ mod tests {
     }
 
     #[test]
+    #[allow(clippy::useless_vec)]
     fn test_format_subtitle() {
         for line_ending in vec!["\n".to_string(), "\r\n".to_string()] {
             let data = SubData {
@@ -526,6 +1401,7 @@ mod tests {
                         time_span: TimeSpan::new(0, 1000),
                         position: None,
                         lines: vec![format!("l1{}", line_ending), format!("l2{}", line_ending)],
+                        ..Default::default()
                     },
                     Subtitle {
                         number: 2,
@@ -537,9 +1413,11 @@ mod tests {
                             y2: 4,
                         }),
                         lines: vec![format!("l3{}", line_ending)],
+                        ..Default::default()
                     },
                 ],
                 line_ending: line_ending.to_owned(),
+                ..Default::default()
             };
             assert_eq!(
                 format!("{}", data),
@@ -558,4 +1436,23 @@ mod tests {
             );
         }
     }
+
+    #[test]
+    fn test_format_from_path() {
+        assert_eq!(
+            Format::from_extension(Path::new("a.SRT")),
+            Some(Format::Srt)
+        );
+        assert_eq!(
+            Format::from_extension(Path::new("a.ssa")),
+            Some(Format::Ass)
+        );
+        assert_eq!(Format::from_extension(Path::new("a.txt")), None);
+        assert_eq!(Format::from_extension(Path::new("-")), None);
+        assert_eq!(Format::detect(Path::new("-"), "WEBVTT\n"), Format::Vtt);
+        assert_eq!(Format::detect(Path::new("-"), "1\n"), Format::Srt);
+
+        assert!(is_std_stream(Path::new("-")));
+        assert!(!is_std_stream(Path::new("./-")));
+    }
 }
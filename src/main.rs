@@ -1,15 +1,18 @@
+use std::ffi::OsStr;
 use std::fs::rename;
 use std::fs::File;
-use std::io::BufRead;
 use std::io::BufReader;
-use std::io::BufWriter;
 use std::io::ErrorKind;
+use std::io::Read;
 use std::io::Write;
 use std::path::Path;
+use std::process::Child;
 use std::process::Command;
+use std::process::ExitStatus;
+use std::process::Stdio;
 
-use anyhow::{anyhow, bail, Context, Result};
-use encoding_rs_io::DecodeReaderBytes;
+use anyhow::{bail, Context, Result};
+use encoding_rs::Encoding;
 use lazy_static::lazy_static;
 use log::LevelFilter;
 #[allow(unused_imports)]
@@ -17,20 +20,144 @@ use log::{debug, error, info, trace, warn};
 use regex::Regex;
 use structopt::StructOpt;
 
-mod lib;
-use crate::lib::*;
+use subtitle_adjust::encoding::TextEncoding;
+use subtitle_adjust::*;
 
 fn main() -> Result<()> {
     let opt = init()?;
+    if opt.check {
+        return check(&opt);
+    }
+
+    if let [path] = opt.paths.as_slice() {
+        return process(path, &opt);
+    }
+
+    // Every file is tried, and the ones that failed are listed at the end:
+    let mut failed = 0;
+    for path in &opt.paths {
+        if opt.dry_run {
+            println!("==> {} <==", path.display());
+        }
+        match process(path, &opt) {
+            Ok(()) => eprintln!("Done: {}", path.display()),
+            Err(err) => {
+                failed += 1;
+                eprintln!("Failed: {}: {:#}", path.display(), err);
+            }
+        }
+    }
+    if failed > 0 {
+        bail!("{} of {} files failed.", failed, opt.paths.len());
+    }
+    eprintln!("All {} files are done.", opt.paths.len());
+    Ok(())
+}
+
+/// Report the problems in all the input files, and exit with a code for the worst one.
+fn check(opt: &OptFinal) -> Result<()> {
+    let reports: Vec<check::Report> = opt
+        .paths
+        .iter()
+        .map(|path| {
+            let findings = read_text(path, opt.input_encoding)
+                .and_then(|(text, _)| {
+                    let data = SubData::parse(&text, Format::detect(path, &text), opt.fps)?;
+                    Ok(check::check(&text, &data))
+                })
+                .unwrap_or_else(|err| vec![check::Finding::unreadable(&err)]);
+            check::Report {
+                file: path.clone(),
+                findings,
+            }
+        })
+        .collect();
+
+    if opt.json {
+        println!("{}", check::to_json(&reports));
+    } else {
+        print!("{}", check::to_text(&reports));
+    }
+    std::io::stdout().flush()?;
+    std::process::exit(check::exit_code(&reports));
+}
+
+/// Make the changes to one input file.
+fn process(path: &Path, opt: &OptFinal) -> Result<()> {
+    // The speed and sync options are worked out separately for each file:
+    let mut opt = opt.clone();
 
     if opt.extract {
-        extract_subtitles(&opt.path)
+        extract_subtitles(path)
     } else {
-        let mut subs = get_subtitles(&opt.path).context("Error processing subtitles")?;
+        let mut subs = get_subtitles(path, opt.fps, opt.input_encoding, opt.strict)
+            .context("Error processing subtitles")?;
+        if opt.detect_speed || opt.fix_speed {
+            let estimate = estimate_speed(&subs, &opt)?;
+            if opt.detect_speed {
+                println!("The subtitles need {}.", estimate);
+                return Ok(());
+            }
+            match estimate.known_scale() {
+                Some(scale) => {
+                    info!("Fixing the speed: the subtitles need {}.", estimate);
+                    opt.scale = Some(scale);
+                }
+                None => bail!(
+                    "Can't fix the speed automatically: the subtitles need {}. Use `--scale` instead.",
+                    estimate
+                ),
+            }
+        } else if let Some(path) = &opt.reference {
+            let reference = get_subtitles(path, opt.fps, None, opt.strict)
+                .context("Error processing the reference subtitles")?;
+            opt.sync = reference::find_anchors(&subs.subs, &reference.subs, opt.piecewise)?;
+        }
+        if let Some(path) = &opt.audio {
+            let speech = get_speech(path).context("Error processing the audio")?;
+            opt.sync = audio::find_anchors(&subs.subs, &speech)?;
+        }
+        let before = opt.dry_run.then(|| subs.subs.clone());
         modify(&mut subs, &opt)?;
-        backup(&opt.path)?;
-        if let Err(err) = write_to_disk(subs, &opt.path) {
-            restore(&opt.path)?;
+        if let Some(before) = before {
+            print!("{}", dry_run::changes_table(&before, &subs.subs));
+            return Ok(());
+        }
+
+        let mut output = opt.output.clone().unwrap_or_else(|| path.to_path_buf());
+        // An output file with the extension of another format means converting to it:
+        let output_format = opt.output_format.or_else(|| {
+            opt.output
+                .as_deref()
+                .and_then(Format::from_extension)
+                .filter(|&format| format != subs.format)
+        });
+        if let Some(encoding) = opt.output_encoding {
+            subs.encoding = encoding;
+        }
+        if let Some(format) = output_format {
+            subs.fps = subs.fps.or(opt.fps);
+            for warning in subs.convert(format)? {
+                warn!("{}", warning);
+            }
+            if opt.output.is_none() && !is_std_stream(&output) {
+                output.set_extension(format.extension());
+            }
+        }
+
+        if is_std_stream(&output) {
+            return write_to_stdout(subs);
+        }
+
+        // The input file is always backed up, but a converted file may not exist yet:
+        let backed_up = output.exists();
+        if backed_up {
+            backup(&output)?;
+        }
+        if let Err(err) = write_to_disk(subs, &output) {
+            if backed_up {
+                restore(&output)?;
+            }
             bail!(err);
         }
         Ok(())
@@ -55,24 +182,38 @@ fn init() -> Result<OptFinal> {
 
 /// Extract subtitles to .srt from a video file or other format subtitle.
 /// Needs ffmpeg.
-fn extract_subtitles(path: &std::path::PathBuf) -> Result<()> {
+fn extract_subtitles(path: &Path) -> Result<()> {
     let output = path.with_extension("srt");
+    run_ffmpeg(&[path.as_os_str(), output.as_os_str()], "extract subtitles")?;
+    Ok(())
+}
+
+/// Run ffmpeg on an input file and an output file, with extra arguments for the output.
+fn run_ffmpeg(args: &[&OsStr], purpose: &str) -> Result<ExitStatus> {
+    Ok(spawn_ffmpeg(args, purpose, false)?.wait()?)
+}
+
+/// Start ffmpeg like `run_ffmpeg`, optionally with its standard output piped to this program.
+fn spawn_ffmpeg(args: &[&OsStr], purpose: &str, piped: bool) -> Result<Child> {
+    let (input, output_args) = args.split_first().unwrap();
     // NOTE: If run in WSL, this can invoke ffmpeg.exe if ffmpeg isn't found,
     // but paths may not be valid for Windows executables. It works for paths
     // without leading directory parts.
     for executable in ["ffmpeg", "ffmpeg.exe"] {
         let mut command = Command::new(executable);
+        if piped {
+            command.stdout(Stdio::piped());
+        }
         let handle = command
             .arg("-i")
-            .arg(path)
+            .arg(input)
             .arg("-loglevel")
             .arg("quiet")
-            .arg(&output)
+            .args(output_args)
             .spawn();
         match handle {
-            Ok(mut handle) => {
-                handle.wait()?;
-                return Ok(());
+            Ok(handle) => {
+                return Ok(handle);
             }
             Err(err) if err.kind() == ErrorKind::NotFound => {
                 info!("Will try to continue after error: {}", err);
@@ -81,102 +222,108 @@ fn extract_subtitles(path: &std::path::PathBuf) -> Result<()> {
             Err(err) => bail!(err),
         };
     }
-    bail!("Cannot extract subtitles: could not find `ffmpeg` or `ffmpeg.exe`.");
+    bail!(
+        "Cannot {}: could not find `ffmpeg` or `ffmpeg.exe`.",
+        purpose
+    );
 }
 
-fn get_subtitles(path: &std::path::PathBuf) -> Result<SubData> {
-    info!("Opening input file: {:#?}", &path);
-    let file = File::open(&path)?;
-    // This library will detect the encoding and remove the BOM if present:
-    let decoder = DecodeReaderBytes::new(file);
-    let mut reader = BufReader::new(decoder);
-
-    let mut subs = Vec::new();
-    let mut line_ending = None;
-
-    let mut part_number: Option<i64> = None;
-    let mut part_times: Option<TimeSpan> = None; // each is milliseconds
-    let mut part_position: Option<Position> = None;
-    let mut part_lines: Option<Vec<String>> = None;
-
-    loop {
-        let mut buf = String::new();
-        if let Ok(0) = reader.read_line(&mut buf) {
-            if part_lines.is_some() {
-                subs.push(Subtitle {
-                    number: part_number.take().unwrap(),
-                    time_span: part_times.take().unwrap(),
-                    position: part_position.take(),
-                    lines: part_lines.take().unwrap(),
-                });
-            }
-            return Ok(SubData {
-                subs,
-                line_ending: line_ending.unwrap_or("\n".to_string()),
-            });
+/// Find the speech in a WAV file, or in any audio or video file that ffmpeg can read.
+fn get_speech(path: &Path) -> Result<Vec<bool>> {
+    info!("Opening audio file: {:#?}", &path);
+    let is_wav = path
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("wav"));
+    let loudness = if is_wav {
+        audio::read_loudness(BufReader::new(File::open(path)?))?
+    } else {
+        // Mono at 16 kHz is plenty to find speech. The samples are read from ffmpeg's output as
+        // they come, so nothing is written to disk:
+        let mut child = spawn_ffmpeg(
+            &[
+                path.as_os_str(),
+                OsStr::new("-vn"),
+                OsStr::new("-ac"),
+                OsStr::new("1"),
+                OsStr::new("-ar"),
+                OsStr::new("16000"),
+                OsStr::new("-f"),
+                OsStr::new("f32le"),
+                OsStr::new("-"),
+            ],
+            "read the audio",
+            true,
+        )?;
+        let stdout = child.stdout.take().unwrap();
+        let loudness = audio::read_raw_loudness(BufReader::new(stdout), 16000);
+        if !child.wait()?.success() {
+            bail!("ffmpeg could not read the audio from {:#?}.", path);
         }
-        line_ending.get_or_insert_with(|| {
-            if buf.ends_with("\r\n") {
-                "\r\n".to_string()
-            } else {
-                "\n".to_string()
-            }
-        });
+        loudness?
+    };
+    Ok(audio::detect_speech(&loudness))
+}
 
-        if buf.trim().is_empty() {
-            if part_lines.is_some() {
-                // a Subtitle struct is now finished
-                if part_lines.is_some() {
-                    subs.push(Subtitle {
-                        number: part_number.take().unwrap(),
-                        time_span: part_times.take().unwrap(),
-                        position: part_position.take(),
-                        lines: part_lines.take().unwrap(),
-                    });
-                }
-            }
-        } else if part_number.is_none() {
-            part_number = Some(
-                buf.trim()
-                    .parse::<i64>()
-                    .with_context(|| format!("Was expecting integer, found {:#?}", buf))?,
-            );
-        } else if part_times.is_none() {
-            // looking for 00:00:08,614 --> 00:00:10,373
-            // or          00:00:08,614 --> 00:00:10,373  X1:201 X2:516 Y1:397 Y2:423
-            // Negative timestamps are not part of the standard AFAIK, but they need to be created
-            // and parsed so moving a subtitle back too far doesn't permanently remove its timing data.
-            lazy_static! {
-                static ref RE: Regex =
-                    Regex::new(r"(.*) --> (.*)(\s+X1:(-?\d+) X2:(-?\d+) Y1:(-?\d+) Y2:(-?\d+))?")
-                        .unwrap();
-            }
-            let captures = RE
-                .captures(&buf)
-                .with_context(|| format!("Expecting time --> time; got: {:#?}", buf))?;
-            let start_ms = parse_ms(
-                captures
-                    .get(1)
-                    .ok_or(anyhow!("Missing start time"))?
-                    .as_str(),
-            );
-            let end_ms = parse_ms(captures.get(2).ok_or(anyhow!("Missing end time"))?.as_str());
-            part_times = Some(TimeSpan::new(start_ms?, end_ms?));
-
-            part_position = if captures.get(3).is_some() {
-                Some(Position {
-                    x1: captures.get(4).unwrap().as_str().parse()?,
-                    x2: captures.get(5).unwrap().as_str().parse()?,
-                    y1: captures.get(6).unwrap().as_str().parse()?,
-                    y2: captures.get(7).unwrap().as_str().parse()?,
-                })
-            } else {
-                None
-            };
-        } else {
-            part_lines.get_or_insert_with(|| vec![]).push(buf);
-        }
+/// Compare the subtitles with the reference subtitles or the video length to find the speed
+/// they need.
+fn estimate_speed(subs: &SubData, opt: &OptFinal) -> Result<speed::Estimate> {
+    if let Some(duration) = opt.video_duration {
+        return Ok(speed::from_duration(&subs.subs, duration));
     }
+    let path = opt.reference.as_ref().unwrap();
+    let reference = get_subtitles(path, opt.fps, None, opt.strict)
+        .context("Error processing the reference subtitles")?;
+    let anchors = reference::find_anchors(&subs.subs, &reference.subs, false)?;
+    Ok(speed::from_anchors(anchors[0], anchors[1]))
+}
+
+/// Read subtitles in the given text encoding, or the detected one. Mistakes in the file are
+/// warned about, or refused if `strict` is set.
+fn get_subtitles(
+    path: &Path,
+    fps: Option<f64>,
+    encoding: Option<&'static Encoding>,
+    strict: bool,
+) -> Result<SubData> {
+    let (text, encoding) = read_text(path, encoding)?;
+    let mut data = SubData::parse(&text, Format::detect(path, &text), fps)?;
+    data.encoding = encoding;
+
+    let problems: Vec<String> = data.problems.iter().map(|p| p.to_string()).collect();
+    if strict && !problems.is_empty() {
+        bail!(
+            "Found {} mistakes in {:#?}:\n{}",
+            problems.len(),
+            path,
+            problems.join("\n")
+        );
+    }
+    const MAX_WARNINGS: usize = 10;
+    for problem in problems.iter().take(MAX_WARNINGS) {
+        warn!("{}, {}", path.display(), problem);
+    }
+    if problems.len() > MAX_WARNINGS {
+        warn!(
+            "{} more mistakes were worked around. Use `--strict` to list them all.",
+            problems.len() - MAX_WARNINGS
+        );
+    }
+    Ok(data)
+}
+
+/// Read a file or standard input, in the given text encoding or the detected one. The
+/// encoding and BOM are returned too, so the file can be written back the same way.
+fn read_text(path: &Path, encoding: Option<&'static Encoding>) -> Result<(String, TextEncoding)> {
+    let mut reader: Box<dyn Read> = if is_std_stream(path) {
+        info!("Reading standard input");
+        Box::new(std::io::stdin())
+    } else {
+        info!("Opening input file: {:#?}", &path);
+        Box::new(File::open(path)?)
+    };
+    let mut bytes = vec![];
+    reader.read_to_end(&mut bytes)?;
+    Ok(encoding::decode(&bytes, encoding))
 }
 
 fn backup(path: &Path) -> Result<()> {
@@ -197,13 +344,52 @@ fn restore(path: &Path) -> Result<()> {
 
 fn modify(data: &mut SubData, opt: &OptFinal) -> Result<()> {
     info!("Applying changes to the subtitle in memory.");
-
-    for i in 0..data.subs.len() {
-        let ref mut sub = data.subs[i];
-        if opt.renumber_offset {
-            sub.number = (i + 1) as i64;
+    if opt.strip_hi {
+        hearing_impaired::strip(data, &opt.hi_patterns);
+    }
+    if let Some(keep) = opt.strip_tags {
+        tags::strip_tags(data, keep);
+    }
+    if !opt.replacements.is_empty() {
+        replace::replace(data, &opt.replacements);
+    }
+    // Times given in frames count the frames of the subtitle file, if it has them:
+    let fps = data.fps.or(opt.fps);
+    if let Some(max_chars) = opt.merge_short {
+        split_merge::merge_short(data, max_chars, opt.merge_gap.to_ms(fps)?);
+    }
+    if let Some(max_chars) = opt.split_long {
+        split_merge::split_long(data, max_chars);
+    }
+    if let Some(max_chars) = opt.rewrap {
+        for warning in wrap::rewrap(&mut data.subs, max_chars, &data.line_ending) {
+            warn!("{}", warning);
         }
+    }
 
+    let offset_ms = opt.offset.to_ms(fps)?;
+    let offset_start_ms = opt
+        .offset_start
+        .map_or(Ok(i64::MIN), |offset_start| offset_start.to_ms(fps))?;
+    let scale_pivot_ms = opt.scale_pivot.map(|pivot| pivot.to_ms(fps)).transpose()?;
+    let shifts = opt
+        .shift
+        .iter()
+        .map(|shift| Ok((&shift.range, shift.offset.to_ms(fps)?)))
+        .collect::<Result<Vec<_>>>()?;
+    let before: Vec<TimeSpan> = data.subs.iter().map(|sub| sub.time_span.clone()).collect();
+    for pair in opt.sync.windows(2) {
+        let (scale, offset) = sync::linear_mapping(pair[0], pair[1]);
+        info!(
+            "Syncing from {} with a scale of {} and an offset of {} ms.",
+            Milliseconds(pair[0].from_ms),
+            scale,
+            offset
+        );
+    }
+
+    let ssa = ass::is_ssa(&data.header);
+    for sub in data.subs.iter_mut() {
         // Move the subtitle up or down if needed:
         if opt
             .to_top
@@ -214,31 +400,76 @@ fn modify(data: &mut SubData, opt: &OptFinal) -> Result<()> {
                 bail!("Cannot override subtitle position information at {} because it has hard coded position.", sub.time_span.start_ms);
             }
 
-            // Add a position tag at the beginning, replacing any existing position tag:
-            lazy_static! {
-                static ref RE: Regex = Regex::new(r"^(\{\\an\d+\})?").unwrap();
+            match data.format {
+                Format::Vtt => {
+                    // WebVTT has a cue setting for this; line 0 is the top of the video.
+                    vtt::set_line_setting(&mut sub.settings, Some("0"));
+                }
+                Format::Ass => {
+                    if let Some(first_line) = sub.lines.first_mut() {
+                        *first_line = ass::set_alignment(first_line, Some(8), ssa);
+                    }
+                }
+                Format::MicroDvd => {
+                    bail!("Moving subtitles to the top is not supported for MicroDVD files.")
+                }
+                Format::Srt => {
+                    // Add a position tag at the beginning, replacing any existing position tag:
+                    lazy_static! {
+                        static ref RE: Regex = Regex::new(r"^(\{\\an\d+\})?").unwrap();
+                    }
+                    // A subtitle without text has nothing to move:
+                    if let Some(first_line) = sub.lines.first_mut() {
+                        *first_line = RE.replace(first_line.as_str(), r"{\an8}").to_string();
+                    }
+                }
             }
-            sub.lines[0] = RE.replace(sub.lines[0].as_str(), r"{\an8}").to_string();
         } else if opt
             .to_bottom
             .iter()
             .any(|interval| interval.contains(sub.time_span.start_ms))
         {
-            // Remove any hard coded coordinates and any {\anX} positions:
+            // Remove any hard coded coordinates, WebVTT line settings and any {\anX} positions:
             sub.position.take();
-            lazy_static! {
-                static ref RE: Regex = Regex::new(r"^\{\\an\d+\}").unwrap();
+            vtt::set_line_setting(&mut sub.settings, None);
+            if data.format == Format::Ass {
+                // Removing the tag isn't enough if the subtitle's style is not at the bottom:
+                let alignment = if ass::style_is_bottom(&data.header, sub) {
+                    None
+                } else {
+                    Some(2)
+                };
+                if let Some(first_line) = sub.lines.first_mut() {
+                    *first_line = ass::set_alignment(first_line, alignment, ssa);
+                }
+            } else {
+                lazy_static! {
+                    static ref RE: Regex = Regex::new(r"^\{\\an\d+\}").unwrap();
+                }
+                if let Some(first_line) = sub.lines.first_mut() {
+                    *first_line = RE.replace(first_line.as_str(), "").to_string();
+                }
             }
-            sub.lines[0] = RE.replace(sub.lines[0].as_str(), "").to_string();
         }
 
         // Apply the offset (if it's active at the current time):
-        if sub.time_span.start_ms >= opt.offset_start_ms {
-            sub.time_span.start_ms += opt.offset_ms;
-            sub.time_span.end_ms += opt.offset_ms;
+        if !opt.sync.is_empty() {
+            sub.time_span.start_ms = sync::map_time(&opt.sync, sub.time_span.start_ms);
+            sub.time_span.end_ms = sync::map_time(&opt.sync, sub.time_span.end_ms);
+        } else if !shifts.is_empty() {
+            if let Some((_, offset_ms)) = shifts
+                .iter()
+                .find(|(range, _)| range.contains(sub.time_span.start_ms))
+            {
+                sub.time_span.start_ms += offset_ms;
+                sub.time_span.end_ms += offset_ms;
+            }
+        } else if sub.time_span.start_ms >= offset_start_ms {
+            sub.time_span.start_ms += offset_ms;
+            sub.time_span.end_ms += offset_ms;
 
             if let Some(scale) = opt.scale {
-                let pivot = opt.scale_pivot.unwrap_or_default();
+                let pivot = scale_pivot_ms.unwrap_or_default();
                 sub.time_span.start_ms =
                     pivot + (scale * (sub.time_span.start_ms - pivot) as f64) as i64;
                 sub.time_span.end_ms =
@@ -246,12 +477,68 @@ fn modify(data: &mut SubData, opt: &OptFinal) -> Result<()> {
             }
         }
     }
+
+    if opt.fix_overlaps {
+        overlaps::fix_overlaps(data, opt.min_gap.to_ms(fps)?);
+    } else if !shifts.is_empty() {
+        // Shifting one range of subtitles shouldn't make them run into another range:
+        let after: Vec<TimeSpan> = data.subs.iter().map(|sub| sub.time_span.clone()).collect();
+        if let Some(&(i, j)) = new_collisions(&before, &after).first() {
+            bail!(
+                "After shifting, subtitle {} ({}) runs into subtitle {} ({}).",
+                data.subs[i].number,
+                data.subs[i].time_span,
+                data.subs[j].number,
+                data.subs[j].time_span
+            );
+        }
+    }
+
+    let limits = duration::Limits {
+        min_ms: opt.min_duration.map(|time| time.to_ms(fps)).transpose()?,
+        max_ms: opt.max_duration.map(|time| time.to_ms(fps)).transpose()?,
+        max_cps: opt.max_cps,
+    };
+    // Frames are only comparable with times once the frame rate is known:
+    if let (Some(min_ms), Some(max_ms)) = (limits.min_ms, limits.max_ms) {
+        if min_ms > max_ms {
+            bail!("`--min-duration` can't be longer than `--max-duration`.");
+        }
+    }
+    if !limits.is_empty() {
+        for warning in duration::enforce(&mut data.subs, limits, opt.min_gap.to_ms(fps)?) {
+            warn!("{}", warning);
+        }
+    }
+
+    // Numbering is done last, so it follows any new order:
+    if opt.renumber_offset {
+        for (i, sub) in data.subs.iter_mut().enumerate() {
+            sub.number = (i + 1) as i64;
+            // WebVTT identifiers that are numbers are numbered again too:
+            if sub
+                .identifier
+                .as_ref()
+                .is_some_and(|id| id.parse::<i64>().is_ok())
+            {
+                sub.identifier = Some(sub.number.to_string());
+            }
+        }
+    }
+    Ok(())
+}
+
+fn write_to_stdout(data: SubData) -> Result<()> {
+    info!("Writing modified subtitle to standard output");
+    let mut stdout = std::io::stdout().lock();
+    stdout.write_all(&encoding::encode(&data.to_string(), data.encoding))?;
+    stdout.flush()?;
     Ok(())
 }
 
 fn write_to_disk(data: SubData, path: &Path) -> Result<()> {
     info!("Writing modified subtitle to disk: {:#?}", path);
-    let file = File::create(path)?;
-    write!(BufWriter::new(file), "{}", data)?;
+    let mut file = File::create(path)?;
+    file.write_all(&encoding::encode(&data.to_string(), data.encoding))?;
     Ok(())
 }
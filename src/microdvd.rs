@@ -0,0 +1,168 @@
+use anyhow::{bail, Context, Result};
+use lazy_static::lazy_static;
+use log::warn;
+use regex::Regex;
+
+use crate::frame_rate::FrameRate;
+use crate::{Format, SubData, Subtitle, TimeSpan};
+
+lazy_static! {
+    // looking for {1025}{1100}Some text|second line
+    static ref CUE: Regex = Regex::new(r"^\{(-?\d+)\}\{(-?\d+)\}(.*)$").unwrap();
+}
+
+/// Does this look like a MicroDVD line?
+pub(crate) fn is_cue(line: &str) -> bool {
+    CUE.is_match(line.trim())
+}
+
+/// Parse the text of a MicroDVD (.sub) file. The times in the file are frame numbers, so a
+/// frame rate is needed. It's taken from `fps` if given, otherwise from a `{1}{1}23.976`
+/// header line.
+pub(crate) fn parse(text: &str, fps: Option<f64>) -> Result<SubData> {
+    let line_ending = if text.split('\n').next().unwrap_or("").ends_with('\r') {
+        "\r\n"
+    } else {
+        "\n"
+    };
+
+    let mut header = vec![];
+    let mut header_fps = None;
+    let mut cues = vec![];
+    for line in text.lines() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let captures = CUE
+            .captures(line.trim())
+            .with_context(|| format!("Expecting {{frame}}{{frame}}text; got: {:#?}", line))?;
+        let start: i64 = captures[1].parse()?;
+        let end: i64 = captures[2].parse()?;
+        let text = &captures[3];
+
+        // The first line may give the frame rate instead of a subtitle:
+        if cues.is_empty() && header.is_empty() && start == end && start <= 1 {
+            if let Ok(rate) = text.parse::<FrameRate>() {
+                header_fps = Some(rate.as_f64());
+                header.push(format!("{}{}", line, line_ending));
+                continue;
+            }
+        }
+        cues.push((start, end, text.to_string()));
+    }
+
+    let fps = match (fps, header_fps) {
+        (Some(fps), Some(header_fps)) if fps != header_fps => {
+            warn!(
+                "Using a frame rate of {} instead of {} from the file header.",
+                fps, header_fps
+            );
+            fps
+        }
+        (Some(fps), _) | (None, Some(fps)) => fps,
+        (None, None) => {
+            bail!("The MicroDVD file does not give a frame rate. Use `--fps` to set one.")
+        }
+    };
+    if fps <= 0.0 {
+        bail!("Invalid frame rate: {}", fps);
+    }
+
+    let subs = cues
+        .into_iter()
+        .enumerate()
+        .map(|(i, (start, end, text))| Subtitle {
+            number: i as i64 + 1,
+            time_span: TimeSpan::new(frames_to_ms(start, fps), frames_to_ms(end, fps)),
+            lines: text
+                .split('|')
+                .map(|line| format!("{}{}", line, line_ending))
+                .collect(),
+            ..Default::default()
+        })
+        .collect();
+
+    Ok(SubData {
+        subs,
+        line_ending: line_ending.to_string(),
+        format: Format::MicroDvd,
+        header,
+        fps: Some(fps),
+        ..Default::default()
+    })
+}
+
+pub(crate) fn write(data: &SubData, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    // Parsing and converting to MicroDVD both make sure the frame rate is known:
+    let fps = data.fps.expect("MicroDVD subtitles need a frame rate");
+    for line in data.header.iter() {
+        f.write_str(line)?;
+    }
+    for sub in data.subs.iter() {
+        let text: Vec<&str> = sub
+            .lines
+            .iter()
+            .map(|line| line.trim_end_matches(&['\r', '\n'][..]))
+            .collect();
+        write!(
+            f,
+            "{{{}}}{{{}}}{}{}",
+            ms_to_frames(sub.time_span.start_ms, fps),
+            ms_to_frames(sub.time_span.end_ms, fps),
+            text.join("|"),
+            data.line_ending
+        )?;
+    }
+    Ok(())
+}
+
+pub fn frames_to_ms(frames: i64, fps: f64) -> i64 {
+    (frames as f64 * 1000.0 / fps).round() as i64
+}
+
+pub fn ms_to_frames(ms: i64, fps: f64) -> i64 {
+    (ms as f64 * fps / 1000.0).round() as i64
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Format, SubData, TimeSpan};
+
+    use super::{frames_to_ms, ms_to_frames};
+
+    #[test]
+    fn test_parse_microdvd() {
+        let text = "{1}{1}25\n{25}{50}Hello|{y:i}world\n\n{100}{125}Bye\n";
+        let data = SubData::parse(text, Format::MicroDvd, None).unwrap();
+        assert_eq!(data.fps, Some(25.0));
+        assert_eq!(data.header, vec!["{1}{1}25\n"]);
+        assert_eq!(data.subs.len(), 2);
+        assert_eq!(data.subs[0].time_span, TimeSpan::new(1000, 2000));
+        assert_eq!(data.subs[0].lines, vec!["Hello\n", "{y:i}world\n"]);
+        assert_eq!(data.subs[1].number, 2);
+
+        // The frame rate on the command line wins:
+        let data = SubData::parse(text, Format::MicroDvd, Some(50.0)).unwrap();
+        assert_eq!(data.subs[0].time_span, TimeSpan::new(500, 1000));
+
+        assert!(SubData::parse("{25}{50}Hello\n", Format::MicroDvd, None).is_err());
+        assert!(SubData::parse("{25}{50}Hello\n", Format::MicroDvd, Some(25.0)).is_ok());
+        assert!(SubData::parse("1\n", Format::MicroDvd, Some(25.0)).is_err());
+    }
+
+    #[test]
+    fn test_microdvd_round_trip() {
+        let text = "{1}{1}23.976\r\n{0}{48}Hello|world\r\n{1000}{1100}Bye\r\n";
+        let data = SubData::parse(text, Format::MicroDvd, None).unwrap();
+        assert_eq!(format!("{}", data), text);
+    }
+
+    #[test]
+    fn test_frame_conversion() {
+        assert_eq!(frames_to_ms(48, 23.976), 2002);
+        assert_eq!(ms_to_frames(2002, 23.976), 48);
+        for frame in 0..5000 {
+            assert_eq!(ms_to_frames(frames_to_ms(frame, 29.97), 29.97), frame);
+        }
+    }
+}
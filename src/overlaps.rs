@@ -0,0 +1,114 @@
+//! Fixing subtitles that are shown at the same time, for `--fix-overlaps`. Players often
+//! draw those on top of each other, or hide one of them.
+
+use lazy_static::lazy_static;
+use log::info;
+use regex::Regex;
+
+use crate::{ass, Format, Position, SubData, Subtitle};
+
+/// Sort the subtitles by start time, and end each one at least `min_gap_ms` before the next
+/// one starts. Subtitles that start too close together for that are merged into one, with
+/// the lines of both. Subtitles shown in different places, like a sign at the top of the
+/// screen during dialogue, are meant to overlap and are left alone.
+pub fn fix_overlaps(data: &mut SubData, min_gap_ms: i64) {
+    data.subs.sort_by_key(|sub| sub.time_span.start_ms);
+
+    let mut trimmed = 0;
+    let mut merged = 0;
+    let mut fixed: Vec<Subtitle> = Vec::with_capacity(data.subs.len());
+    for sub in std::mem::take(&mut data.subs) {
+        let place = placement(data, &sub);
+        // Earlier subtitles in the same place already end before this one does:
+        let previous = fixed
+            .iter_mut()
+            .rev()
+            .find(|previous| placement(data, previous) == place);
+        if let Some(previous) = previous {
+            let end_ms = sub.time_span.start_ms - min_gap_ms;
+            if previous.time_span.end_ms > end_ms {
+                if end_ms > previous.time_span.start_ms {
+                    previous.time_span.end_ms = end_ms;
+                    trimmed += 1;
+                } else {
+                    previous.time_span.end_ms = previous.time_span.end_ms.max(sub.time_span.end_ms);
+                    previous.lines.extend(sub.lines);
+                    previous.preceding.extend(sub.preceding);
+                    merged += 1;
+                    continue;
+                }
+            }
+        }
+        fixed.push(sub);
+    }
+    data.subs = fixed;
+    info!(
+        "Fixed overlaps by shortening {} subtitles and merging {} into others.",
+        trimmed, merged
+    );
+}
+
+/// Where a subtitle is shown: its coordinates, WebVTT settings, alignment tag, and for ASS
+/// files, whether its style is at the bottom.
+pub(crate) fn placement(
+    data: &SubData,
+    sub: &Subtitle,
+) -> (Option<Position>, Option<String>, Option<String>, bool) {
+    lazy_static! {
+        static ref ALIGNMENT: Regex = Regex::new(r"^\{[^}]*\\an(\d)").unwrap();
+    }
+    let alignment = sub
+        .lines
+        .first()
+        .and_then(|line| ALIGNMENT.captures(line))
+        .map(|captures| captures[1].to_string());
+    let bottom = data.format != Format::Ass || ass::style_is_bottom(&data.header, sub);
+    (
+        sub.position.clone(),
+        sub.settings.clone(),
+        alignment,
+        bottom,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_subs::sub;
+    use crate::{Format, SubData, TimeSpan};
+
+    use super::fix_overlaps;
+
+    #[test]
+    fn test_fix_overlaps() {
+        let mut data = SubData {
+            subs: vec![
+                sub(1, 1000, 3000, "One"),
+                sub(3, 5000, 7000, "Three"),
+                sub(2, 2500, 4000, "Two"),
+                sub(4, 5050, 6000, "Also three"),
+                sub(5, 6500, 8000, "{\\an8}A sign"),
+                sub(6, 8000, 9000, "Touching"),
+            ],
+            format: Format::Srt,
+            ..Default::default()
+        };
+        fix_overlaps(&mut data, 80);
+
+        let subs: Vec<(i64, TimeSpan, usize)> = data
+            .subs
+            .iter()
+            .map(|sub| (sub.number, sub.time_span.clone(), sub.lines.len()))
+            .collect();
+        assert_eq!(
+            subs,
+            vec![
+                (1, TimeSpan::new(1000, 2420), 1),
+                (2, TimeSpan::new(2500, 4000), 1),
+                (3, TimeSpan::new(5000, 7000), 2),
+                (5, TimeSpan::new(6500, 8000), 1),
+                (6, TimeSpan::new(8000, 9000), 1),
+            ]
+        );
+        assert_eq!(data.subs[2].lines, vec!["Three\n", "Also three\n"]);
+    }
+}
@@ -0,0 +1,439 @@
+//! Automatic syncing against a reference subtitle file that is already timed correctly, for
+//! example a subtitle in another language. Subtitles are matched up by their start times and
+//! durations, which mostly don't depend on the language.
+
+use std::collections::HashMap;
+
+use anyhow::{bail, Result};
+
+use crate::speed;
+use crate::sync::{validate_anchors, Anchor};
+use crate::{Subtitle, TimeSpan};
+
+/// Offsets are grouped into bins of this many milliseconds when voting.
+const BIN_MS: f64 = 100.0;
+
+/// How far apart matched subtitles may start, after syncing.
+const MATCH_TOLERANCE_MS: f64 = 500.0;
+
+/// Changing to a different offset when syncing piecewise costs this many unmatched subtitles.
+const SWITCH_COST: f64 = 3.0;
+
+/// Work out the `--sync` anchors that move the subtitles onto the reference subtitles. With
+/// `piecewise`, different parts of the file may get different offsets, which is needed when
+/// scenes were added or removed.
+pub fn find_anchors(
+    subs: &[Subtitle],
+    reference: &[Subtitle],
+    piecewise: bool,
+) -> Result<Vec<Anchor>> {
+    let spans: Vec<&TimeSpan> = subs.iter().map(|sub| &sub.time_span).collect();
+    let mut reference: Vec<&TimeSpan> = reference.iter().map(|sub| &sub.time_span).collect();
+    reference.sort_by_key(|span| span.start_ms);
+    if spans.len() < 2 || reference.len() < 2 {
+        bail!("At least two subtitles are needed in both files to sync to a reference.");
+    }
+
+    // For each speed, the offsets between all similar looking pairs of subtitles are counted,
+    // and the most common offset wins:
+    let (scale, histogram) = speed::scales()
+        .map(|scale| (scale, offset_histogram(&spans, &reference, scale)))
+        .max_by(|(_, a), (_, b)| best_bin(a).1.total_cmp(&best_bin(b).1))
+        .unwrap();
+    let (bin, votes) = best_bin(&histogram);
+    if votes < 2.0 {
+        bail!("The subtitles don't look like the reference subtitles, so they can't be synced to them.");
+    }
+
+    let anchors = if piecewise {
+        piecewise_anchors(&spans, &reference, scale, &histogram)
+    } else {
+        let (scale, offset) = refine(&spans, &reference, scale, bin as f64 * BIN_MS);
+        let first = spans.iter().map(|span| span.start_ms).min().unwrap();
+        let last = spans.iter().map(|span| span.start_ms).max().unwrap();
+        let map = |ms: i64| (scale * ms as f64 + offset).round() as i64;
+        vec![Anchor::new(first, map(first)), Anchor::new(last, map(last))]
+    };
+
+    validate_anchors(&anchors)?;
+    let matched = spans
+        .iter()
+        .filter(|span| {
+            nearest(
+                &reference,
+                crate::sync::map_time(&anchors, span.start_ms) as f64,
+            )
+            .is_some()
+        })
+        .count();
+    if matched * 5 < spans.len() {
+        bail!(
+            "Only {} of {} subtitles could be matched with the reference subtitles, so they can't be synced.",
+            matched,
+            spans.len()
+        );
+    }
+    Ok(anchors)
+}
+
+/// Do two durations look like they belong to the same line?
+fn similar_duration(a: f64, b: f64) -> bool {
+    (a - b).abs() <= f64::max(200.0, 0.25 * a.max(b))
+}
+
+fn duration(span: &TimeSpan) -> f64 {
+    (span.end_ms - span.start_ms) as f64
+}
+
+/// Count the offsets from every subtitle to every reference subtitle with a similar duration.
+fn offset_histogram(spans: &[&TimeSpan], reference: &[&TimeSpan], scale: f64) -> HashMap<i64, f64> {
+    let mut histogram = HashMap::new();
+    for span in spans {
+        for other in reference {
+            if similar_duration(scale * duration(span), duration(other)) {
+                let offset = other.start_ms as f64 - scale * span.start_ms as f64;
+                *histogram
+                    .entry((offset / BIN_MS).round() as i64)
+                    .or_insert(0.0) += 1.0;
+            }
+        }
+    }
+    histogram
+}
+
+/// Votes for a bin, including the neighbouring bins since an offset can fall near the edge.
+fn bin_votes(histogram: &HashMap<i64, f64>, bin: i64) -> f64 {
+    (bin - 1..=bin + 1)
+        .map(|bin| histogram.get(&bin).copied().unwrap_or_default())
+        .sum()
+}
+
+/// The bin with the most votes, and its votes.
+fn best_bin(histogram: &HashMap<i64, f64>) -> (i64, f64) {
+    histogram
+        .keys()
+        .map(|&bin| (bin, bin_votes(histogram, bin)))
+        // Break ties by bin so the result doesn't depend on the hash order:
+        .max_by(|a, b| a.1.total_cmp(&b.1).then(b.0.cmp(&a.0)))
+        .unwrap_or((0, 0.0))
+}
+
+/// The reference subtitle that starts closest to the given time, if it's close enough.
+fn nearest<'a>(reference: &[&'a TimeSpan], ms: f64) -> Option<&'a TimeSpan> {
+    let i = reference.partition_point(|span| (span.start_ms as f64) < ms);
+    reference[i.saturating_sub(1)..(i + 1).min(reference.len())]
+        .iter()
+        .min_by(|a, b| {
+            (a.start_ms as f64 - ms)
+                .abs()
+                .total_cmp(&(b.start_ms as f64 - ms).abs())
+        })
+        .filter(|span| (span.start_ms as f64 - ms).abs() <= MATCH_TOLERANCE_MS)
+        .copied()
+}
+
+/// Improve a rough scale and offset with a least squares fit of the matched subtitles.
+fn refine(
+    spans: &[&TimeSpan],
+    reference: &[&TimeSpan],
+    mut scale: f64,
+    mut offset: f64,
+) -> (f64, f64) {
+    for _ in 0..3 {
+        let pairs: Vec<(f64, f64)> = spans
+            .iter()
+            .filter_map(|span| {
+                let mapped = scale * span.start_ms as f64 + offset;
+                nearest(reference, mapped)
+                    .filter(|other| similar_duration(scale * duration(span), duration(other)))
+                    .map(|other| (span.start_ms as f64, other.start_ms as f64))
+            })
+            .collect();
+        if pairs.len() < 2 {
+            break;
+        }
+
+        let n = pairs.len() as f64;
+        let mean_x = pairs.iter().map(|p| p.0).sum::<f64>() / n;
+        let mean_y = pairs.iter().map(|p| p.1).sum::<f64>() / n;
+        let covariance: f64 = pairs.iter().map(|p| (p.0 - mean_x) * (p.1 - mean_y)).sum();
+        let variance: f64 = pairs.iter().map(|p| (p.0 - mean_x).powi(2)).sum();
+        if variance > 0.0 {
+            scale = covariance / variance;
+        }
+        offset = mean_y - scale * mean_x;
+    }
+    (scale, offset)
+}
+
+/// Give each subtitle one of the common offsets, preferring offsets that match it with a
+/// reference subtitle but changing offsets as rarely as possible. Each run of subtitles with
+/// the same offset becomes a pair of anchors, at the first and last subtitle it matched.
+fn piecewise_anchors(
+    spans: &[&TimeSpan],
+    reference: &[&TimeSpan],
+    scale: f64,
+    histogram: &HashMap<i64, f64>,
+) -> Vec<Anchor> {
+    // The candidate offsets are the peaks of the histogram with enough votes:
+    let threshold = f64::max(3.0, spans.len() as f64 * 0.02);
+    let mut bins: Vec<(i64, f64)> = histogram
+        .keys()
+        .map(|&bin| (bin, bin_votes(histogram, bin)))
+        .filter(|&(_, votes)| votes >= threshold)
+        .collect();
+    bins.sort_by(|a, b| b.1.total_cmp(&a.1).then(a.0.cmp(&b.0)));
+    let mut peaks: Vec<(i64, f64)> = vec![];
+    for (bin, votes) in bins {
+        // Neighbouring bins share their votes, so only the best of them is a peak:
+        if peaks.len() < 10 && peaks.iter().all(|&(peak, _)| (peak - bin).abs() > 2) {
+            peaks.push((bin, votes));
+        }
+    }
+    if peaks.is_empty() {
+        // Short files may not have enough votes for any peak:
+        peaks.push(best_bin(histogram));
+    }
+    let offsets: Vec<f64> = peaks.iter().map(|&(bin, _)| bin as f64 * BIN_MS).collect();
+
+    let mut order: Vec<&TimeSpan> = spans.to_vec();
+    order.sort_by_key(|span| span.start_ms);
+    let matches = |span: &TimeSpan, scale: f64, offset: f64| {
+        nearest(reference, scale * span.start_ms as f64 + offset)
+            .is_some_and(|other| similar_duration(scale * duration(span), duration(other)))
+    };
+
+    // Viterbi: the cost of each offset for the subtitles so far, and how it was reached.
+    let mut costs: Vec<f64> = vec![0.0; offsets.len()];
+    let mut back: Vec<Vec<usize>> = vec![];
+    for span in &order {
+        let cheapest = (0..offsets.len())
+            .min_by(|&a, &b| costs[a].total_cmp(&costs[b]))
+            .unwrap();
+        let mut from = vec![0; offsets.len()];
+        let mut next = vec![0.0; offsets.len()];
+        for (k, &offset) in offsets.iter().enumerate() {
+            let (previous, cost) = if costs[cheapest] + SWITCH_COST < costs[k] {
+                (cheapest, costs[cheapest] + SWITCH_COST)
+            } else {
+                (k, costs[k])
+            };
+            from[k] = previous;
+            next[k] = cost
+                + if matches(span, scale, offset) {
+                    0.0
+                } else {
+                    1.0
+                };
+        }
+        back.push(from);
+        costs = next;
+    }
+    let mut label = (0..offsets.len())
+        .min_by(|&a, &b| costs[a].total_cmp(&costs[b]))
+        .unwrap();
+    let mut labels = vec![0; order.len()];
+    for i in (0..order.len()).rev() {
+        labels[i] = label;
+        label = back[i][label];
+    }
+
+    // Each run of subtitles is anchored at the first and last subtitle in it that was matched,
+    // and the offset is refined with the matched subtitles. The subtitles between two runs are
+    // spread over the gap, so where a scene was removed they collapse onto the cut:
+    let mut anchors: Vec<Anchor> = vec![];
+    let mut start = 0;
+    while start < order.len() {
+        let end = start
+            + labels[start..]
+                .iter()
+                .take_while(|&&l| l == labels[start])
+                .count();
+        let run = &order[start..end];
+        let (run_scale, run_offset) = refine(run, reference, scale, offsets[labels[start]]);
+        let (run_scale, run_offset) = if run.len() >= 10 {
+            (run_scale, run_offset)
+        } else {
+            // Too few subtitles to trust a new scale:
+            refine_offset(run, reference, scale, offsets[labels[start]])
+        };
+        let matched: Vec<&&TimeSpan> = run
+            .iter()
+            .filter(|span| matches(span, run_scale, run_offset))
+            .collect();
+        for span in [matched.first(), matched.last()].iter().flatten() {
+            let mut anchor = Anchor::new(
+                span.start_ms,
+                (run_scale * span.start_ms as f64 + run_offset).round() as i64,
+            );
+            if let Some(previous) = anchors.last() {
+                if anchor.from_ms <= previous.from_ms {
+                    continue;
+                }
+                // The times must keep increasing in the video too, even where matched
+                // subtitles of two runs are close together:
+                anchor.to_ms = anchor.to_ms.max(previous.to_ms + 1);
+            }
+            anchors.push(anchor);
+        }
+        start = end;
+    }
+    anchors
+}
+
+/// Improve the offset alone, keeping the scale.
+fn refine_offset(
+    spans: &[&TimeSpan],
+    reference: &[&TimeSpan],
+    scale: f64,
+    offset: f64,
+) -> (f64, f64) {
+    let differences: Vec<f64> = spans
+        .iter()
+        .filter_map(|span| {
+            let mapped = scale * span.start_ms as f64 + offset;
+            nearest(reference, mapped).map(|other| other.start_ms as f64 - mapped)
+        })
+        .collect();
+    if differences.is_empty() {
+        return (scale, offset);
+    }
+    (
+        scale,
+        offset + differences.iter().sum::<f64>() / differences.len() as f64,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::sync::map_time;
+    use crate::test_subs::dialogue;
+    use crate::{Subtitle, TimeSpan};
+
+    use super::find_anchors;
+
+    fn reference() -> Vec<Subtitle> {
+        dialogue(300, 5_000, 200)
+    }
+
+    /// Move subtitles with a function, so they need to be synced back.
+    fn distort(subs: &[Subtitle], f: impl Fn(i64) -> i64) -> Vec<Subtitle> {
+        subs.iter()
+            .map(|sub| Subtitle {
+                number: sub.number,
+                time_span: TimeSpan::new(f(sub.time_span.start_ms), f(sub.time_span.end_ms)),
+                ..Default::default()
+            })
+            .collect()
+    }
+
+    /// Check that each subtitle is synced to the start of the expected one.
+    fn assert_synced(subs: &[Subtitle], expected: &[Subtitle], piecewise: bool) {
+        let anchors = find_anchors(subs, &reference(), piecewise).unwrap();
+        for (sub, expected) in subs.iter().zip(expected) {
+            let synced = map_time(&anchors, sub.time_span.start_ms);
+            assert!(
+                (synced - expected.time_span.start_ms).abs() <= 20,
+                "{} was synced to {} instead of {}",
+                sub.time_span.start_ms,
+                synced,
+                expected.time_span.start_ms
+            );
+        }
+    }
+
+    #[test]
+    fn test_offset_and_scale() {
+        let reference = reference();
+        let subs = distort(&reference, |ms| ms - 3_500);
+        assert_synced(&subs, &reference, false);
+
+        let subs = distort(&reference, |ms| (ms as f64 * 25.0 / 23.976) as i64 + 1_200);
+        assert_synced(&subs, &reference, false);
+    }
+
+    #[test]
+    fn test_piecewise() {
+        // The reference is for a cut with scenes added at 400 and 800 seconds:
+        let reference = reference();
+        let added = |ms: i64| (400_000..445_000).contains(&ms) || (800_000..825_000).contains(&ms);
+        let kept: Vec<Subtitle> = distort(&reference, |ms| ms)
+            .into_iter()
+            .filter(|sub| !added(sub.time_span.start_ms))
+            .collect();
+        let subs = distort(&kept, |ms| match ms {
+            ms if ms < 400_000 => ms,
+            ms if ms < 800_000 => ms - 45_000,
+            ms => ms - 70_000,
+        });
+        assert_synced(&subs, &kept, true);
+
+        // A single offset can't fit all of it:
+        let anchors = find_anchors(&subs, &reference, false).unwrap();
+        assert!(subs.iter().zip(&kept).any(|(sub, expected)| (map_time(
+            &anchors,
+            sub.time_span.start_ms
+        ) - expected.time_span.start_ms)
+            .abs()
+            > 20_000));
+
+        // The other way around, with a one minute scene removed at 200 seconds:
+        let subs = reference.clone();
+        let cut = |ms: i64| (200_000..260_000).contains(&ms);
+        let kept: Vec<Subtitle> = subs
+            .iter()
+            .filter(|sub| !cut(sub.time_span.start_ms))
+            .cloned()
+            .collect();
+        let video = distort(&kept, |ms| if ms < 200_000 { ms } else { ms - 60_000 });
+        let anchors = find_anchors(&subs, &video, true).unwrap();
+        for sub in &subs {
+            let start_ms = sub.time_span.start_ms;
+            let synced = map_time(&anchors, start_ms);
+            if cut(start_ms) {
+                assert!(
+                    (195_000..205_000).contains(&synced),
+                    "{} was synced to {}",
+                    start_ms,
+                    synced
+                );
+            } else {
+                let expected = if start_ms < 200_000 {
+                    start_ms
+                } else {
+                    start_ms - 60_000
+                };
+                assert!(
+                    (synced - expected).abs() <= 20,
+                    "{} was synced to {} instead of {}",
+                    start_ms,
+                    synced,
+                    expected
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_piecewise_few_subtitles() {
+        let reference = distort(&reference()[..2], |ms| ms);
+        let subs = distort(&reference, |ms| ms + 2_000);
+        let anchors = find_anchors(&subs, &reference, true).unwrap();
+        assert_eq!(
+            map_time(&anchors, subs[1].time_span.start_ms),
+            reference[1].time_span.start_ms
+        );
+    }
+
+    #[test]
+    fn test_unrelated() {
+        let reference = reference();
+        let subs: Vec<Subtitle> = (0..50)
+            .map(|i| Subtitle {
+                time_span: TimeSpan::new(i * 10_000, i * 10_000 + 60_000),
+                ..Default::default()
+            })
+            .collect();
+        assert!(find_anchors(&subs, &reference, false).is_err());
+    }
+}
@@ -0,0 +1,161 @@
+//! Finding and replacing text in subtitles with regular expressions, for `--replace` and
+//! `--replace-file`. Useful for fixing names, recurring OCR mistakes, or filtering words.
+
+use std::path::Path;
+
+use anyhow::{anyhow, Context, Result};
+use log::info;
+use regex::Regex;
+
+use crate::{is_timespan, parse_timespan, SubData, TimeSpan};
+
+/// A pattern to replace, in the subtitles that start in a time range, if one is given.
+#[derive(Debug, Clone)]
+pub struct Replacement {
+    pub pattern: Regex,
+    /// The text to put instead, where `$1` or `${name}` is a group of the pattern.
+    pub replacement: String,
+    pub range: Option<TimeSpan>,
+}
+
+/// Parse a replacement like `colour=color`, or `(?i)\bjon\b=John@10:00-20:00` with a time
+/// range after the last `@`. The pattern ends at the first `=`.
+pub fn parse_replacement(input: &str) -> Result<Replacement> {
+    let (rule, range) = match input.rsplit_once('@') {
+        Some((rule, range)) if is_timespan(range) => (rule, Some(parse_timespan(range)?)),
+        _ => (input, None),
+    };
+    let (pattern, replacement) = rule
+        .split_once('=')
+        .filter(|(pattern, _)| !pattern.is_empty())
+        .ok_or_else(|| anyhow!("Expecting PATTERN=REPLACEMENT, got: {:#?}", input))?;
+    Ok(Replacement {
+        pattern: Regex::new(pattern).with_context(|| format!("Invalid pattern in {:#?}", input))?,
+        replacement: replacement.to_string(),
+        range,
+    })
+}
+
+/// Read replacements from a file, one per line, written like for `--replace`. Blank lines and
+/// lines starting with `#` are skipped.
+pub fn read_replacements(path: &Path) -> Result<Vec<Replacement>> {
+    let text = std::fs::read_to_string(path)
+        .with_context(|| format!("Can't read the replacements file {:#?}", path))?;
+    text.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty() && !line.starts_with('#'))
+        .map(|(i, line)| {
+            parse_replacement(line)
+                .with_context(|| format!("Invalid replacement on line {} of {:#?}", i + 1, path))
+        })
+        .collect()
+}
+
+/// Make the replacements, in order, in the text of each subtitle. Patterns can match across
+/// lines, which are separated by `\n`. Lines left empty are removed, and so are subtitles with
+/// no text left.
+pub fn replace(data: &mut SubData, replacements: &[Replacement]) {
+    let line_ending = data.line_ending.clone();
+    let (mut changed, mut removed) = (0, 0);
+    let mut preceding = vec![];
+    let mut kept = Vec::with_capacity(data.subs.len());
+    for mut sub in std::mem::take(&mut data.subs) {
+        let lines: Vec<&str> = sub
+            .lines
+            .iter()
+            .map(|line| line.trim_end_matches(&['\r', '\n'][..]))
+            .collect();
+        let original = lines.join("\n");
+        let mut text = original.clone();
+        for rule in replacements {
+            if rule
+                .range
+                .as_ref()
+                .is_none_or(|range| range.contains(sub.time_span.start_ms))
+            {
+                text = rule
+                    .pattern
+                    .replace_all(&text, rule.replacement.as_str())
+                    .to_string();
+            }
+        }
+        let replaced = text != original;
+        if replaced {
+            changed += 1;
+            sub.lines = text
+                .split('\n')
+                .filter(|line| !line.trim().is_empty())
+                .map(|line| format!("{}{}", line, line_ending))
+                .collect();
+        }
+
+        // Comments or styles before a removed subtitle go before the next one:
+        preceding.append(&mut sub.preceding);
+        if replaced && sub.lines.is_empty() {
+            removed += 1;
+            continue;
+        }
+        sub.preceding = std::mem::take(&mut preceding);
+        kept.push(sub);
+    }
+    preceding.append(&mut data.trailer);
+    data.trailer = preceding;
+    data.subs = kept;
+    info!(
+        "Replaced text in {} subtitles, and removed {} that had no text left.",
+        changed, removed
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_subs::sub;
+    use crate::{SubData, TimeSpan};
+
+    use super::{parse_replacement, replace};
+
+    #[test]
+    fn test_replace() {
+        let rule = parse_replacement(r"(?i)\bjon\b=John@10-20").unwrap();
+        assert_eq!(rule.range, Some(TimeSpan::new(10000, 20000)));
+        let email = parse_replacement("mail=me@example.com").unwrap();
+        assert_eq!(
+            (email.replacement.as_str(), email.range),
+            ("me@example.com", None)
+        );
+        assert!(parse_replacement("no replacement").is_err());
+        assert!(parse_replacement("(unclosed=x").is_err());
+        assert!(parse_replacement("a=b@20-10").is_err());
+        let negative = parse_replacement("a=b@-1:00.5--10").unwrap();
+        assert_eq!(negative.range, Some(TimeSpan::new(-60500, -10000)));
+        assert_eq!(
+            parse_replacement("a=b@-5").unwrap().range,
+            Some(TimeSpan::new(i64::MIN, 5000))
+        );
+
+        let mut data = SubData {
+            subs: vec![
+                sub(1, 5000, 6000, "Jon, the colour"),
+                sub(2, 15000, 16000, "JON, the\ncolour"),
+                sub(3, 16000, 17000, "damn"),
+            ],
+            line_ending: "\n".to_string(),
+            ..Default::default()
+        };
+        let replacements = [
+            rule,
+            parse_replacement(r"(?i)(col)our=${1}or").unwrap(),
+            parse_replacement(r"the\ncolor=the color").unwrap(),
+            parse_replacement(r"damn=").unwrap(),
+        ];
+        replace(&mut data, &replacements);
+        let lines: Vec<&Vec<String>> = data.subs.iter().map(|sub| &sub.lines).collect();
+        assert_eq!(
+            lines,
+            vec![
+                &vec!["Jon, the color\n".to_string()],
+                &vec!["John, the color\n".to_string()],
+            ]
+        );
+    }
+}
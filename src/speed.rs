@@ -0,0 +1,208 @@
+//! Working out whether subtitles were timed for a video with a different frame rate, which
+//! makes them drift further off the longer the video plays.
+
+use std::fmt;
+
+use crate::sync::{linear_mapping, Anchor};
+use crate::Subtitle;
+
+/// A common playback speed difference between two releases of the same video.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct SpeedChange {
+    /// How the frame rates are usually written, as the subtitles' rate over the video's.
+    pub name: &'static str,
+    pub scale: f64,
+}
+
+/// Speed changes that come from converting video between frame rates. 23.976 is really
+/// 24000/1001, so 24/23.976 and 30/29.97 are the same ratio, 1001/1000.
+pub const SPEED_CHANGES: &[SpeedChange] = &[
+    SpeedChange {
+        name: "25/23.976",
+        scale: 25.0 * 1001.0 / 24000.0,
+    },
+    SpeedChange {
+        name: "23.976/25",
+        scale: 24000.0 / 1001.0 / 25.0,
+    },
+    SpeedChange {
+        name: "25/24",
+        scale: 25.0 / 24.0,
+    },
+    SpeedChange {
+        name: "24/25",
+        scale: 24.0 / 25.0,
+    },
+    SpeedChange {
+        name: "24/23.976 or 30/29.97",
+        scale: 1.001,
+    },
+    SpeedChange {
+        name: "23.976/24 or 29.97/30",
+        scale: 1.0 / 1.001,
+    },
+];
+
+/// The scales to try when syncing automatically: no change, or one of the common ones.
+pub(crate) fn scales() -> impl Iterator<Item = f64> {
+    std::iter::once(1.0).chain(SPEED_CHANGES.iter().map(|change| change.scale))
+}
+
+/// A reference scale estimate is trusted this closely. The closest known ratios, 25/23.976
+/// and 25/24, are 0.1% apart.
+const REFERENCE_TOLERANCE: f64 = 0.0003;
+
+/// A video can run this much longer than its last subtitle, for the end credits.
+const CREDITS_SLACK: f64 = 0.04;
+
+/// What was found out about the speed of the subtitles.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Estimate {
+    /// The scale that would fix the subtitles.
+    pub scale: f64,
+    /// When only the video length is known, the scale is the largest one that makes sense.
+    pub upper_bound: bool,
+    /// The known speed changes that fit. No change at all is an empty list with `in_sync`.
+    pub matches: Vec<SpeedChange>,
+    pub in_sync: bool,
+}
+
+impl Estimate {
+    /// The single scale to apply, if the estimate isn't ambiguous.
+    pub fn known_scale(&self) -> Option<f64> {
+        match (self.in_sync, self.matches.as_slice()) {
+            (true, []) => Some(1.0),
+            (false, [change]) => Some(change.scale),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Estimate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut names: Vec<&str> = self.matches.iter().map(|change| change.name).collect();
+        if self.in_sync {
+            names.insert(0, "no speed change");
+        }
+        if self.upper_bound {
+            f.write_str("at most ")?;
+        }
+        write!(f, "a scale of {:.5}, ", self.scale)?;
+        match names.as_slice() {
+            [] => f.write_str("which is not a common frame rate change"),
+            [name] => write!(f, "which matches {}", name),
+            _ => write!(f, "which could be {}", names.join(" or ")),
+        }
+    }
+}
+
+/// Estimate the speed from subtitles that were synced to a reference, given the `--sync`
+/// anchors that did it.
+pub fn from_anchors(first: Anchor, last: Anchor) -> Estimate {
+    let (scale, _) = linear_mapping(first, last);
+    let close = |known: f64| (scale / known - 1.0).abs() <= REFERENCE_TOLERANCE;
+    Estimate {
+        scale,
+        upper_bound: false,
+        matches: SPEED_CHANGES
+            .iter()
+            .copied()
+            .filter(|change| close(change.scale))
+            .collect(),
+        in_sync: close(1.0),
+    }
+}
+
+/// Estimate the speed from the length of the video. The subtitles have to end before the
+/// video does, but the end credits can take a while, so this is only a rough guess.
+pub fn from_duration(subs: &[Subtitle], duration_ms: i64) -> Estimate {
+    let last = subs
+        .iter()
+        .map(|sub| sub.time_span.end_ms)
+        .max()
+        .unwrap_or(0);
+    let scale = duration_ms as f64 / last.max(1) as f64;
+    let fits = |known: f64| {
+        known <= scale * (1.0 + REFERENCE_TOLERANCE) && scale <= known * (1.0 + CREDITS_SLACK)
+    };
+    Estimate {
+        scale,
+        upper_bound: true,
+        matches: SPEED_CHANGES
+            .iter()
+            .copied()
+            .filter(|change| fits(change.scale))
+            .collect(),
+        in_sync: fits(1.0),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::frame_rate::FrameRate;
+    use crate::sync::Anchor;
+    use crate::{Subtitle, TimeSpan};
+
+    use super::{from_anchors, from_duration};
+
+    #[test]
+    fn test_from_anchors() {
+        let estimate = from_anchors(Anchor::new(0, 1000), Anchor::new(5_754_240, 6_001_000));
+        assert_eq!(estimate.matches.len(), 1);
+        assert_eq!(estimate.matches[0].name, "25/23.976");
+        assert_eq!(
+            estimate.known_scale(),
+            Some(FrameRate::PAL.ratio(FrameRate::NTSC))
+        );
+        assert_eq!(
+            estimate.to_string(),
+            "a scale of 1.04271, which matches 25/23.976"
+        );
+
+        let estimate = from_anchors(Anchor::new(0, 0), Anchor::new(6_000_000, 6_006_000));
+        assert_eq!(estimate.known_scale(), Some(1.001));
+
+        let estimate = from_anchors(Anchor::new(0, 0), Anchor::new(6_000_000, 6_000_300));
+        assert!(estimate.in_sync);
+        assert_eq!(estimate.known_scale(), Some(1.0));
+
+        let estimate = from_anchors(Anchor::new(0, 0), Anchor::new(6_000_000, 6_600_000));
+        assert_eq!(estimate.known_scale(), None);
+        assert!(estimate
+            .to_string()
+            .contains("not a common frame rate change"));
+    }
+
+    #[test]
+    fn test_from_duration() {
+        let subs = vec![Subtitle {
+            time_span: TimeSpan::new(6_600_000, 6_603_000),
+            ..Default::default()
+        }];
+
+        // Timed for PAL, with two minutes of credits after the last line at film speed:
+        let estimate = from_duration(&subs, (6_603_000.0 * 25.0 / 23.976) as i64 + 120_000);
+        let names: Vec<&str> = estimate.matches.iter().map(|change| change.name).collect();
+        assert_eq!(names, vec!["25/23.976", "25/24"]);
+        assert!(!estimate.in_sync);
+        assert_eq!(estimate.known_scale(), None);
+        assert_eq!(
+            estimate.to_string(),
+            "at most a scale of 1.06088, which could be 25/23.976 or 25/24"
+        );
+
+        // Three minutes of credits at the same speed:
+        let estimate = from_duration(&subs, 6_783_000);
+        assert!(estimate.in_sync);
+        assert_eq!(estimate.known_scale(), None);
+
+        // The video is shorter than the subtitles:
+        let estimate = from_duration(&subs, 6_400_000);
+        let names: Vec<&str> = estimate.matches.iter().map(|change| change.name).collect();
+        assert_eq!(names, vec!["23.976/25", "24/25"]);
+        assert!(!estimate.in_sync);
+        assert!(estimate
+            .to_string()
+            .starts_with("at most a scale of 0.96926"));
+    }
+}
@@ -0,0 +1,242 @@
+//! Splitting subtitles with too much text, and merging short ones that follow each other, for
+//! `--split-long` and `--merge-short`. Automatic transcripts often come as whole paragraphs,
+//! or one word at a time.
+
+use std::collections::HashSet;
+
+use lazy_static::lazy_static;
+use log::info;
+use regex::Regex;
+
+use crate::overlaps::placement;
+use crate::tags::TAG;
+use crate::wrap::{self, Segment, CLAUSE_ENDS, CONJUNCTIONS};
+use crate::{SubData, Subtitle};
+
+lazy_static! {
+    /// Formatting at the start of a subtitle that applies to all of it, like `{\an8}` or
+    /// MicroDVD's `{y:i}`.
+    static ref LEADING_TAGS: Regex =
+        Regex::new(r"^(?:\{\\[^}]*\}|\{[a-zA-Z]:[^}]*\})+").unwrap();
+    static ref ITALIC: Regex = Regex::new(r"(?i)<(/?)i>").unwrap();
+}
+
+/// Characters that end a sentence, the best places to split a subtitle.
+const SENTENCE_ENDS: &[char] = &['.', '!', '?', '…', '。', '！', '？'];
+
+/// Split each subtitle with more than `max_width` characters of text into as few parts as
+/// needed, preferably at the end of a sentence. The time is divided between the parts by the
+/// length of their text. The parts keep the position of the subtitle, its alignment tag, and
+/// italics that continue from one part to the next.
+pub fn split_long(data: &mut SubData, max_width: usize) {
+    let line_ending = data.line_ending.clone();
+    let mut split = 0;
+    let mut added = 0;
+    let mut subs = Vec::with_capacity(data.subs.len());
+    for sub in std::mem::take(&mut data.subs) {
+        let parts = split_text(&sub.lines, max_width);
+        if parts.len() < 2 {
+            subs.push(sub);
+            continue;
+        }
+        split += 1;
+        added += parts.len() - 1;
+
+        let leading = sub
+            .lines
+            .first()
+            .and_then(|line| LEADING_TAGS.find(line))
+            .map_or("", |tags| tags.as_str());
+        let widths: Vec<usize> = parts.iter().map(|part| wrap::width(part)).collect();
+        let total = widths.iter().sum::<usize>().max(1) as i64;
+        let (start_ms, duration_ms) = (
+            sub.time_span.start_ms,
+            sub.time_span.end_ms - sub.time_span.start_ms,
+        );
+        let mut done = 0;
+        let mut italic = false;
+        for (i, mut text) in parts.iter().cloned().enumerate() {
+            if italic {
+                text.insert_str(0, "<i>");
+            }
+            if i > 0 && !text.starts_with(leading) {
+                text.insert_str(0, leading);
+            }
+            for tag in ITALIC.captures_iter(&text) {
+                italic = tag[1].is_empty();
+            }
+            if italic && i + 1 < parts.len() {
+                text.push_str("</i>");
+            }
+
+            let mut part = sub.clone();
+            part.time_span.start_ms = start_ms + duration_ms * done / total;
+            done += widths[i] as i64;
+            part.time_span.end_ms = start_ms + duration_ms * done / total;
+            part.lines = text
+                .split('\n')
+                .map(|line| format!("{}{}", line, line_ending))
+                .collect();
+            if i > 0 {
+                // WebVTT identifiers should be unique, and comments only go before the first part.
+                // The other parts are new subtitles:
+                part.identifier = None;
+                part.preceding = vec![];
+                part.index = None;
+            }
+            subs.push(part);
+        }
+    }
+    data.subs = subs;
+    info!("Split {} long subtitles, which made {} more.", split, added);
+}
+
+/// The text of the subtitle in parts of at most `max_width` characters where possible, with
+/// `\n` between lines.
+fn split_text(lines: &[String], max_width: usize) -> Vec<String> {
+    let mut segments: Vec<Segment> = vec![];
+    let mut line_starts = HashSet::new();
+    for line in lines {
+        let mut line_segments = wrap::segments(line.trim_end_matches(&['\r', '\n'][..]));
+        if let Some(first) = line_segments.first_mut() {
+            // The line break takes the place of a space:
+            first.space_before = true;
+            line_starts.insert(segments.len());
+        }
+        segments.append(&mut line_segments);
+    }
+
+    let join = |start: usize, end: usize| {
+        let mut text = String::new();
+        for (i, segment) in segments.iter().enumerate().take(end).skip(start) {
+            if i > start && line_starts.contains(&i) {
+                text.push('\n');
+            } else if i > start && segment.space_before {
+                text.push(' ');
+            }
+            text.push_str(&segment.text);
+        }
+        text
+    };
+    let mut parts = vec![];
+    let mut start = 0;
+    while segments.len() - start > 1 && wrap::line_width(&segments[start..]) > max_width {
+        let end = start + best_split(&segments[start..], max_width);
+        parts.push(join(start, end));
+        start = end;
+    }
+    parts.push(join(start, segments.len()));
+    parts
+}
+
+/// Where to split the text, so that the first part fits, leaves no more text than the other
+/// parts can take, and is about as long as them, preferring the end of a sentence, then other
+/// punctuation, then before a conjunction.
+fn best_split(segments: &[Segment], max_width: usize) -> usize {
+    let total = wrap::line_width(segments);
+    let parts = total.div_ceil(max_width);
+    let target = total / parts;
+    let cost = |k: usize| {
+        let (first, rest) = (
+            wrap::line_width(&segments[..k]),
+            wrap::line_width(&segments[k..]),
+        );
+        // Both the first part and the parts after it should fit:
+        let overflow =
+            first.saturating_sub(max_width) + rest.saturating_sub((parts - 1) * max_width);
+        let before = TAG.replace_all(&segments[k - 1].text, "");
+        let next_word = TAG.replace_all(&segments[k].text, "").to_lowercase();
+        let penalty = if before.ends_with(SENTENCE_ENDS) {
+            0
+        } else if before.ends_with(CLAUSE_ENDS) {
+            max_width / 4
+        } else if CONJUNCTIONS.contains(&next_word.as_str()) {
+            max_width / 3
+        } else {
+            max_width / 2
+        };
+        overflow * 1000 + first.abs_diff(target) + penalty
+    };
+    (1..segments.len()).min_by_key(|&k| cost(k)).unwrap()
+}
+
+/// Merge subtitles into the one before them when they start at most `max_gap_ms` after it
+/// ends, are shown in the same place, and the text of both together has at most `max_width`
+/// characters. The text is added to the last line of the one before.
+pub fn merge_short(data: &mut SubData, max_width: usize, max_gap_ms: i64) {
+    let mut merged = 0;
+    let mut subs: Vec<Subtitle> = Vec::with_capacity(data.subs.len());
+    for sub in std::mem::take(&mut data.subs) {
+        if let Some(previous) = subs.last_mut() {
+            let gap_ms = sub.time_span.start_ms - previous.time_span.end_ms;
+            if (0..=max_gap_ms).contains(&gap_ms)
+                && placement(data, previous) == placement(data, &sub)
+            {
+                if let Some(lines) = merged_lines(previous, &sub, max_width) {
+                    previous.lines = lines;
+                    previous.time_span.end_ms = sub.time_span.end_ms;
+                    previous.preceding.extend(sub.preceding);
+                    merged += 1;
+                    continue;
+                }
+            }
+        }
+        subs.push(sub);
+    }
+    data.subs = subs;
+    info!(
+        "Merged {} short subtitles into the ones before them.",
+        merged
+    );
+}
+
+/// The lines of both subtitles together, or `None` if they have too much text.
+fn merged_lines(previous: &Subtitle, next: &Subtitle, max_width: usize) -> Option<Vec<String>> {
+    let leading = previous
+        .lines
+        .first()
+        .and_then(|line| LEADING_TAGS.find(line))
+        .map_or("", |tags| tags.as_str());
+    let mut lines = previous.lines.clone();
+    let mut next_lines = next.lines.iter();
+    let (last, first) = (lines.pop()?, next_lines.next()?);
+    let content = last.trim_end_matches(&['\r', '\n'][..]);
+    // The alignment was already given by the subtitle before:
+    let first = first.strip_prefix(leading).unwrap_or(first);
+    lines.push(format!("{} {}", content, first.trim_start()));
+    lines.extend(next_lines.cloned());
+
+    let width: usize = lines.iter().map(|line| wrap::width(line.trim_end())).sum();
+    (width + lines.len() - 1 <= max_width).then_some(lines)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Format, SubData};
+
+    use super::{merge_short, split_long};
+
+    #[test]
+    fn test_split_and_merge() {
+        let text = "1\n00:00:00,000 --> 00:00:04,000\n\
+                    {\\an8}<i>This is the first sentence. And here is\n\
+                    another one that goes on.</i>\n\n\
+                    2\n00:00:05,000 --> 00:00:05,400\nJust\n\n\
+                    3\n00:00:05,500 --> 00:00:05,900\none word\n\n\
+                    4\n00:00:06,000 --> 00:00:06,500\nat a time.\n\n\
+                    5\n00:00:08,000 --> 00:00:09,000\nLater.\n";
+        let mut data = SubData::parse(text, Format::Srt, None).unwrap();
+        merge_short(&mut data, 30, 500);
+        split_long(&mut data, 42);
+        assert_eq!(
+            data.to_string(),
+            "1\n00:00:00,000 --> 00:00:01,714\n\
+             {\\an8}<i>This is the first sentence.</i>\n\n\
+             1\n00:00:01,714 --> 00:00:04,000\n\
+             {\\an8}<i>And here is\n\
+             another one that goes on.</i>\n\n\
+             2\n00:00:05,000 --> 00:00:06,500\nJust one word at a time.\n\n\
+             5\n00:00:08,000 --> 00:00:09,000\nLater.\n\n"
+        );
+    }
+}
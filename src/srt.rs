@@ -0,0 +1,313 @@
+use anyhow::Result;
+use lazy_static::lazy_static;
+use regex::Regex;
+
+use crate::{parse_ms, Format, ParseProblem, Position, SubData, Subtitle, TimeSpan};
+
+lazy_static! {
+    // looking for 00:00:08,614 --> 00:00:10,373
+    // or          00:00:08,614 --> 00:00:10,373  X1:201 X2:516 Y1:397 Y2:423
+    // The spacing, the arrow and the time separators are read leniently, and checked later.
+    // Negative timestamps are not part of the standard AFAIK, but they need to be created
+    // and parsed so moving a subtitle back too far doesn't permanently remove its timing data.
+    static ref TIMING: Regex = Regex::new(
+        r"^\s*(-?[\d:.,]+)(\s*-+>\s*)(-?[\d:.,]+)(\s+X1:(-?\d+)\s+X2:(-?\d+)\s+Y1:(-?\d+)\s+Y2:(-?\d+))?\s*$"
+    )
+    .unwrap();
+    static ref STRICT_TIME: Regex = Regex::new(r"^-?\d{2,}:\d{2}:\d{2},\d{3}$").unwrap();
+}
+
+/// Parse the text of a SubRip (.srt) file. Files from the internet are often a bit broken,
+/// so missing numbers, missing or extra blank lines, times like `00:00:01.000` and stray
+/// whitespace are worked around. Each of those is recorded in `SubData::problems`.
+pub(crate) fn parse(text: &str) -> Result<SubData> {
+    let lines: Vec<&str> = text.split_inclusive('\n').collect();
+    let line_ending = if lines.first().is_some_and(|line| line.ends_with("\r\n")) {
+        "\r\n"
+    } else {
+        "\n"
+    };
+    let is_blank = |i: usize| lines[i].trim().is_empty();
+
+    // A subtitle starts at its times, or at a number that comes right before them:
+    let cue_start = |i: usize| -> Option<usize> {
+        if TIMING.is_match(lines[i]) {
+            return Some(i);
+        }
+        lines[i].trim().parse::<i64>().ok()?;
+        let timing = (i + 1..lines.len()).find(|&j| !is_blank(j))?;
+        Some(timing).filter(|&j| TIMING.is_match(lines[j]))
+    };
+
+    let mut subs: Vec<Subtitle> = Vec::new();
+    let mut problems = Vec::new();
+    let mut problem = |line: usize, column: usize, message: String| {
+        problems.push(ParseProblem {
+            line: line + 1,
+            column,
+            message,
+        })
+    };
+    // Whether text lines go to the last subtitle, and whether a blank line came after it:
+    let mut open = false;
+    let mut after_blank = false;
+    // Text of a subtitle with unreadable times is left out with it:
+    let mut skipping = false;
+    let mut i = 0;
+    while i < lines.len() {
+        let line = lines[i];
+        if is_blank(i) {
+            after_blank = true;
+            skipping = false;
+            i += 1;
+            continue;
+        }
+
+        if let Some(timing) = cue_start(i) {
+            if open && !after_blank {
+                problem(i, 1, "Missing blank line before the subtitle".to_string());
+            }
+            let number = if timing == i {
+                problem(i, 1, "Missing subtitle number".to_string());
+                subs.last().map_or(1, |sub| sub.number + 1)
+            } else {
+                if let Some(column) = stray_whitespace(line) {
+                    problem(i, column, "Unexpected whitespace".to_string());
+                }
+                if timing > i + 1 {
+                    problem(
+                        i + 1,
+                        1,
+                        "Blank line between the subtitle number and its times".to_string(),
+                    );
+                }
+                line.trim().parse().unwrap()
+            };
+
+            match parse_timing(lines[timing], |column, message| {
+                problem(timing, column, message)
+            }) {
+                Some((time_span, position)) => {
+                    subs.push(Subtitle {
+                        number,
+                        time_span,
+                        position,
+                        ..Default::default()
+                    });
+                    open = true;
+                }
+                None => {
+                    open = false;
+                    skipping = true;
+                }
+            }
+            after_blank = false;
+            i = timing + 1;
+            continue;
+        }
+
+        if open {
+            if after_blank {
+                problem(i, 1, "Blank line inside the subtitle text".to_string());
+            }
+            let lines = &mut subs.last_mut().unwrap().lines;
+            if line.ends_with('\n') {
+                lines.push(line.to_string());
+            } else {
+                lines.push(format!("{}{}", line, line_ending));
+            }
+        } else if !skipping {
+            problem(
+                i,
+                1,
+                format!(
+                    "Expecting a subtitle number or times, found {:#?}; the line was left out",
+                    line.trim_end()
+                ),
+            );
+        }
+        after_blank = false;
+        i += 1;
+    }
+
+    problems.sort_by_key(|problem| (problem.line, problem.column));
+    Ok(SubData {
+        subs,
+        line_ending: line_ending.to_string(),
+        format: Format::Srt,
+        problems,
+        ..Default::default()
+    })
+}
+
+/// Parse a timing line, reporting anything that isn't exactly like `00:00:01,000 -->
+/// 00:00:02,000`. Returns `None` if the times can't be read at all.
+fn parse_timing(
+    line: &str,
+    mut problem: impl FnMut(usize, String),
+) -> Option<(TimeSpan, Option<Position>)> {
+    let captures = TIMING.captures(line)?;
+    let column = |group: usize| line[..captures.get(group).unwrap().start()].chars().count() + 1;
+    if let Some(column) = stray_whitespace(line) {
+        problem(column, "Unexpected whitespace".to_string());
+    }
+    if &captures[2] != " --> " {
+        problem(
+            column(2),
+            format!(
+                "Expecting \" --> \" between the times, found {:#?}",
+                &captures[2]
+            ),
+        );
+    }
+
+    let mut times = [0; 2];
+    for (time, group) in times.iter_mut().zip([1, 3]) {
+        let text = &captures[group];
+        if !STRICT_TIME.is_match(text) {
+            problem(
+                column(group),
+                format!("Expecting a time like 00:01:02,500, found {:#?}", text),
+            );
+        }
+        // Some files use a colon before the milliseconds too:
+        let lenient = match text.rmatch_indices(':').nth(2) {
+            Some(_) => {
+                let (rest, ms) = text.rsplit_once(':').unwrap();
+                format!("{},{}", rest, ms)
+            }
+            None => text.to_string(),
+        };
+        match parse_ms(&lenient) {
+            Ok(ms) => *time = ms,
+            Err(_) => {
+                problem(
+                    column(group),
+                    format!("Can't read the time {:#?}; the subtitle was left out", text),
+                );
+                return None;
+            }
+        }
+    }
+
+    let position = if captures.get(4).is_some() {
+        let coordinate = |group: usize| captures[group].parse().ok();
+        Some(Position {
+            x1: coordinate(5)?,
+            x2: coordinate(6)?,
+            y1: coordinate(7)?,
+            y2: coordinate(8)?,
+        })
+    } else {
+        None
+    };
+    Some((TimeSpan::new(times[0], times[1]), position))
+}
+
+/// The column of whitespace at the start or end of a line, if there is any.
+fn stray_whitespace(line: &str) -> Option<usize> {
+    let content = line.trim_end_matches(&['\r', '\n'][..]);
+    if content.starts_with(char::is_whitespace) {
+        Some(1)
+    } else if content.ends_with(char::is_whitespace) {
+        Some(content.trim_end().chars().count() + 1)
+    } else {
+        None
+    }
+}
+
+pub(crate) fn write(data: &SubData, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    for sub in data.subs.iter() {
+        write!(f, "{}{}", sub.number, data.line_ending)?; // add the number
+        write!(f, "{}", sub.time_span)?; // add the times
+        if let Some(ref position) = sub.position {
+            write!(f, "  {}", position)?;
+        }
+        write!(f, "{}", data.line_ending)?;
+        for line in sub.lines.iter() {
+            write!(f, "{}", line)?; // add the text
+        }
+        f.write_str(data.line_ending.as_str())?; // add a blank line
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Format, SubData, TimeSpan};
+
+    #[test]
+    fn test_parse_valid() {
+        let text = "1\r\n00:00:01,000 --> 00:00:02,500\r\nHello\r\nthere\r\n\r\n\
+                    2\r\n-00:00:00,500 --> 00:00:03,000  X1:1 X2:2 Y1:3 Y2:4\r\nBye\r\n\r\n";
+        let data = SubData::parse(text, Format::Srt, None).unwrap();
+        assert!(data.problems.is_empty());
+        assert_eq!(data.subs.len(), 2);
+        assert_eq!(data.subs[0].lines, vec!["Hello\r\n", "there\r\n"]);
+        assert_eq!(data.subs[1].time_span, TimeSpan::new(-500, 3000));
+        assert!(data.subs[1].position.is_some());
+        assert_eq!(data.to_string(), text);
+    }
+
+    #[test]
+    fn test_parse_messy() {
+        let text = "garbage\n\
+                    1\n\
+                    00:00:01.000-->00:00:02,000\n\
+                    One\n\
+                    \n\
+                    \n\
+                    still one\n\
+                    2\n\
+                    00:00:03,000 --> 00:00:04:000 \n\
+                    Two\n\
+                    \n\
+                    00:00:05,000 --> 00:00:06,000\n\
+                    Three\n\
+                    \n\
+                    \x20 4\n\
+                    \n\
+                    00:00:07,000 --> 99:99:99,000\n\
+                    Dropped\n\
+                    \n\
+                    5\n\
+                    00:00:09,000 --> 00:00:10,000\n\
+                    Five";
+        let data = SubData::parse(text, Format::Srt, None).unwrap();
+        let subs: Vec<(i64, TimeSpan, Vec<&str>)> = data
+            .subs
+            .iter()
+            .map(|sub| {
+                let lines = sub.lines.iter().map(|line| line.as_str()).collect();
+                (sub.number, sub.time_span.clone(), lines)
+            })
+            .collect();
+        assert_eq!(
+            subs,
+            vec![
+                (1, TimeSpan::new(1000, 2000), vec!["One\n", "still one\n"]),
+                (2, TimeSpan::new(3000, 4000), vec!["Two\n"]),
+                (3, TimeSpan::new(5000, 6000), vec!["Three\n"]),
+                (5, TimeSpan::new(9000, 10000), vec!["Five\n"]),
+            ]
+        );
+
+        let problems: Vec<String> = data.problems.iter().map(|p| p.to_string()).collect();
+        assert_eq!(
+            problems,
+            vec![
+                "line 1, column 1: Expecting a subtitle number or times, found \"garbage\"; the line was left out",
+                "line 3, column 1: Expecting a time like 00:01:02,500, found \"00:00:01.000\"",
+                "line 3, column 13: Expecting \" --> \" between the times, found \"-->\"",
+                "line 7, column 1: Blank line inside the subtitle text",
+                "line 8, column 1: Missing blank line before the subtitle",
+                "line 9, column 18: Expecting a time like 00:01:02,500, found \"00:00:04:000\"",
+                "line 9, column 30: Unexpected whitespace",
+                "line 12, column 1: Missing subtitle number",
+                "line 15, column 1: Unexpected whitespace",
+                "line 16, column 1: Blank line between the subtitle number and its times",
+                "line 17, column 18: Can't read the time \"99:99:99,000\"; the subtitle was left out",
+            ]
+        );
+    }
+}
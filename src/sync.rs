@@ -0,0 +1,146 @@
+use anyhow::{anyhow, bail, Result};
+
+use crate::parse_ms;
+
+/// A time in the subtitles and the time in the video that it should be moved to.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Anchor {
+    pub from_ms: i64,
+    pub to_ms: i64,
+}
+
+impl Anchor {
+    pub fn new(from_ms: i64, to_ms: i64) -> Self {
+        Self { from_ms, to_ms }
+    }
+}
+
+/// Parse a pair of times like 10.5=12, where the first is the subtitle time and the second
+/// is the video time.
+pub(crate) fn parse_anchor(input: &str) -> Result<Anchor> {
+    let (from, to) = input
+        .split_once('=')
+        .ok_or_else(|| anyhow!("Expecting subtitle_time=video_time, got: {:#?}", input))?;
+    Ok(Anchor::new(parse_ms(from)?, parse_ms(to)?))
+}
+
+/// Check that the anchors describe a usable time mapping. They must be sorted by subtitle time.
+pub(crate) fn validate_anchors(anchors: &[Anchor]) -> Result<()> {
+    if anchors.len() < 2 {
+        bail!("At least two `--sync` times are needed, one near the start and one near the end.");
+    }
+    for pair in anchors.windows(2) {
+        if pair[0].from_ms == pair[1].from_ms {
+            bail!("The `--sync` subtitle times must all be different.");
+        }
+        if pair[0].to_ms >= pair[1].to_ms {
+            bail!(
+                "The `--sync` times are in a different order in the subtitles than in the video."
+            );
+        }
+    }
+    Ok(())
+}
+
+/// The scale and the offset that map the first anchor onto the second. The offset is applied
+/// after scaling from time 0.
+pub fn linear_mapping(first: Anchor, second: Anchor) -> (f64, f64) {
+    let scale = (second.to_ms - first.to_ms) as f64 / (second.from_ms - first.from_ms) as f64;
+    let offset = first.to_ms as f64 - scale * first.from_ms as f64;
+    (scale, offset)
+}
+
+/// Move a subtitle time to where it belongs in the video. Each stretch between two anchors is
+/// moved and scaled separately; times before the first anchor or after the last one follow
+/// the nearest stretch.
+pub fn map_time(anchors: &[Anchor], ms: i64) -> i64 {
+    let segment = anchors[1..anchors.len() - 1]
+        .iter()
+        .take_while(|anchor| anchor.from_ms <= ms)
+        .count();
+    let (scale, offset) = linear_mapping(anchors[segment], anchors[segment + 1]);
+    (scale * ms as f64 + offset).round() as i64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{linear_mapping, map_time, parse_anchor, validate_anchors, Anchor};
+
+    #[test]
+    fn test_parse_anchor() {
+        assert_eq!(
+            parse_anchor("0:10.5=0:12.0").unwrap(),
+            Anchor::new(10500, 12000)
+        );
+        assert_eq!(
+            parse_anchor("1:45:00=1:46:30").unwrap(),
+            Anchor::new(6_300_000, 6_390_000)
+        );
+        assert_eq!(parse_anchor("-1=2").unwrap(), Anchor::new(-1000, 2000));
+        assert!(parse_anchor("10").is_err());
+        assert!(parse_anchor("10=").is_err());
+    }
+
+    #[test]
+    fn test_validate_anchors() {
+        let a = Anchor::new(10_000, 12_000);
+        assert!(validate_anchors(&[a]).is_err());
+        assert!(validate_anchors(&[a, Anchor::new(10_000, 20_000)]).is_err());
+        assert!(validate_anchors(&[a, Anchor::new(20_000, 11_000)]).is_err());
+        assert!(validate_anchors(&[a, Anchor::new(20_000, 22_000)]).is_ok());
+        assert!(validate_anchors(&[Anchor::new(20_000, 22_000), a]).is_err());
+        assert!(
+            validate_anchors(&[a, Anchor::new(20_000, 22_000), Anchor::new(30_000, 60_000)])
+                .is_ok()
+        );
+        assert!(
+            validate_anchors(&[a, Anchor::new(20_000, 22_000), Anchor::new(30_000, 21_000)])
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_map_time() {
+        let anchors = [
+            Anchor::new(10_500, 12_000),
+            Anchor::new(6_300_000, 6_390_000),
+        ];
+        assert_eq!(map_time(&anchors, 10_500), 12_000);
+        assert_eq!(map_time(&anchors, 6_300_000), 6_390_000);
+
+        // A PAL to film speed change with a 2 second delay:
+        let anchors = [Anchor::new(0, 2000), Anchor::new(25_000, 25_976)];
+        let (scale, offset) = linear_mapping(anchors[0], anchors[1]);
+        assert!((scale - 0.95904).abs() < 1e-9);
+        assert_eq!(offset, 2000.0);
+        assert_eq!(map_time(&anchors, 50_000), 49_952);
+    }
+
+    #[test]
+    fn test_map_time_piecewise() {
+        // A one minute scene was inserted at 10:00 and a 30 second scene at 20:00:
+        let anchors = [
+            Anchor::new(0, 0),
+            Anchor::new(600_000, 660_000),
+            Anchor::new(1_200_000, 1_290_000),
+        ];
+        assert_eq!(map_time(&anchors, -1000), -1100);
+        assert_eq!(map_time(&anchors, 300_000), 330_000);
+        assert_eq!(map_time(&anchors, 600_000), 660_000);
+        assert_eq!(map_time(&anchors, 900_000), 975_000);
+        assert_eq!(map_time(&anchors, 1_200_000), 1_290_000);
+        assert_eq!(map_time(&anchors, 1_800_000), 1_920_000);
+
+        // Only the anchors on both sides of a time matter:
+        let anchors = [
+            Anchor::new(0, 1000),
+            Anchor::new(10_000, 11_000),
+            Anchor::new(20_000, 31_000),
+            Anchor::new(30_000, 41_000),
+        ];
+        assert_eq!(map_time(&anchors, 5000), 6000);
+        assert_eq!(map_time(&anchors, 15_000), 21_000);
+        assert_eq!(map_time(&anchors, 25_000), 36_000);
+        assert_eq!(map_time(&anchors, 40_000), 51_000);
+    }
+}
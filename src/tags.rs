@@ -0,0 +1,264 @@
+//! Removing formatting tags from the text of subtitles, for `--strip-tags`. Some players
+//! show tags like `<font color="red">` as text.
+
+use lazy_static::lazy_static;
+use log::info;
+use regex::{Captures, Regex};
+
+use crate::check::plain_text;
+use crate::{Format, SubData};
+
+lazy_static! {
+    /// HTML style tags like `<i>` or `<font color="red">`, WebVTT timestamps like
+    /// `<00:01.500>`, and blocks in braces like `{\an8}` or MicroDVD's `{y:i}`.
+    pub(crate) static ref TAG: Regex = Regex::new(r"</?[a-zA-Z][^<>]*>|<\d[\d:.]*>|\{[^{}]*\}").unwrap();
+    static ref HTML_ITALIC: Regex = Regex::new(r"^<\s*(/?)\s*[iI]\s*>$").unwrap();
+    static ref MICRODVD_CODE: Regex = Regex::new(r"^\{[a-zA-Z]:[^}]*\}$").unwrap();
+    static ref ALIGNMENT: Regex = Regex::new(r"^an\d$").unwrap();
+    static ref ASS_ITALIC: Regex = Regex::new(r"^i([01]?)$").unwrap();
+}
+
+/// The tags to keep when stripping the others.
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub struct Keep {
+    pub italics: bool,
+    /// Alignment tags like `{\an8}`, which `--to-top` and `--to-bottom` use.
+    pub position: bool,
+}
+
+/// Remove the formatting tags from the subtitles, except the kinds to keep. Italics that are
+/// kept are written the usual way for the format, and unbalanced or empty ones are fixed.
+/// Subtitles that were only tags are removed.
+pub fn strip_tags(data: &mut SubData, keep: Keep) {
+    let format = data.format;
+    let mut removed = 0;
+    let mut preceding = vec![];
+    let mut kept = Vec::with_capacity(data.subs.len());
+    for mut sub in std::mem::take(&mut data.subs) {
+        let mut text: Vec<String> = sub
+            .lines
+            .iter()
+            .map(|line| {
+                let line = line.trim_end_matches(&['\r', '\n'][..]);
+                TAG.replace_all(line, |tag: &Captures| replace_tag(&tag[0], format, keep))
+                    .trim_end()
+                    .to_string()
+            })
+            .collect();
+        if keep.italics && format != Format::Ass {
+            text = normalize_italics(&text.join("\n"))
+                .split('\n')
+                .map(str::to_string)
+                .collect();
+            if format == Format::MicroDvd {
+                text = microdvd_italics(&text);
+            }
+        }
+
+        let had_content = sub.lines.iter().any(|line| !line.trim().is_empty());
+        let lines = std::mem::take(&mut sub.lines);
+        for (line, new_text) in lines.iter().zip(text) {
+            let content = line.trim_end_matches(&['\r', '\n'][..]);
+            // Lines that were only tags are left out:
+            if new_text.trim().is_empty() && !content.trim().is_empty() {
+                continue;
+            }
+            sub.lines
+                .push(format!("{}{}", new_text, &line[content.len()..]));
+        }
+
+        // Comments or styles before a removed subtitle go before the next one:
+        preceding.append(&mut sub.preceding);
+        if had_content && sub.lines.iter().all(|line| plain_text(line).is_empty()) {
+            removed += 1;
+            continue;
+        }
+        sub.preceding = std::mem::take(&mut preceding);
+        kept.push(sub);
+    }
+    preceding.append(&mut data.trailer);
+    data.trailer = preceding;
+    data.subs = kept;
+    if removed > 0 {
+        info!("Removed {} subtitles that only had tags.", removed);
+    }
+}
+
+/// What to write instead of a tag.
+fn replace_tag(tag: &str, format: Format, keep: Keep) -> String {
+    let italic = |closing: bool| match (format, closing) {
+        (Format::Ass, false) => "{\\i1}".to_string(),
+        (Format::Ass, true) => "{\\i0}".to_string(),
+        (_, false) => "<i>".to_string(),
+        (_, true) => "</i>".to_string(),
+    };
+
+    if let Some(captures) = HTML_ITALIC.captures(tag) {
+        return if keep.italics {
+            italic(!captures[1].is_empty())
+        } else {
+            String::new()
+        };
+    }
+    if format == Format::MicroDvd && MICRODVD_CODE.is_match(tag) {
+        return if keep.italics && tag.eq_ignore_ascii_case("{y:i}") {
+            tag.to_string()
+        } else {
+            String::new()
+        };
+    }
+    if let Some(block) = tag.strip_prefix("{\\") {
+        // An ASS override block, like {\an8\i1\c&H0000FF&}:
+        let mut alignment = None;
+        let mut italics = None;
+        for item in block.trim_end_matches('}').split('\\') {
+            if keep.position && ALIGNMENT.is_match(item) {
+                alignment = Some(item);
+            } else if let Some(captures) = ASS_ITALIC.captures(item).filter(|_| keep.italics) {
+                italics = Some(&captures[1] == "0");
+            }
+        }
+        if format == Format::Ass {
+            let kept: String = alignment
+                .into_iter()
+                .map(|item| format!("\\{}", item))
+                .chain(italics.map(|closing| format!("\\i{}", if closing { 0 } else { 1 })))
+                .collect();
+            return if kept.is_empty() {
+                kept
+            } else {
+                format!("{{{}}}", kept)
+            };
+        }
+        let alignment = alignment.map_or(String::new(), |item| format!("{{\\{}}}", item));
+        return alignment + &italics.map_or(String::new(), italic);
+    }
+    if tag.starts_with('{') && format != Format::Ass {
+        // Braces are only comments in ASS files; elsewhere they may be part of the text:
+        return tag.to_string();
+    }
+    String::new()
+}
+
+/// MicroDVD can only make whole lines italic, with `{y:i}` at the start, so each line with
+/// any italic text is made italic instead of using HTML tags.
+fn microdvd_italics(lines: &[String]) -> Vec<String> {
+    lazy_static! {
+        static ref ITALIC: Regex = Regex::new(r"</?i>").unwrap();
+    }
+    let mut open = false;
+    lines
+        .iter()
+        .map(|line| {
+            let mut italic = open;
+            for tag in ITALIC.find_iter(line) {
+                open = tag.as_str() == "<i>";
+                italic |= open;
+            }
+            let line = ITALIC.replace_all(line, "");
+            if italic && !line.to_lowercase().starts_with("{y:i}") {
+                format!("{{y:i}}{}", line)
+            } else {
+                line.to_string()
+            }
+        })
+        .collect()
+}
+
+/// Remove italic tags that are nested, closed without being opened, or empty, join italics
+/// that follow each other, and close any that are left open.
+fn normalize_italics(text: &str) -> String {
+    lazy_static! {
+        static ref ITALIC: Regex = Regex::new(r"</?i>").unwrap();
+        static ref EMPTY: Regex = Regex::new(r"<i>(\s*)</i>").unwrap();
+        static ref ADJACENT: Regex = Regex::new(r"</i>(\s*)<i>").unwrap();
+    }
+    let mut open = false;
+    let mut balanced = ITALIC
+        .replace_all(text, |tag: &Captures| {
+            let closing = &tag[0] == "</i>";
+            if open == closing {
+                open = !open;
+                tag[0].to_string()
+            } else {
+                String::new()
+            }
+        })
+        .to_string();
+    if open {
+        balanced.push_str("</i>");
+    }
+    let balanced = EMPTY.replace_all(&balanced, "$1");
+    ADJACENT.replace_all(&balanced, "$1").to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Format, SubData};
+
+    use super::{strip_tags, Keep};
+
+    fn strip(text: &str, format: Format, italics: bool, position: bool) -> String {
+        let mut data = SubData::parse(text, format, Some(25.0)).unwrap();
+        strip_tags(&mut data, Keep { italics, position });
+        data.to_string()
+    }
+
+    #[test]
+    fn test_strip_srt() {
+        let text = "1\n00:00:01,000 --> 00:00:02,000\n\
+                    {\\an8}<I>Hello</I> <font color=\"#ff0000\">world</font>\n\
+                    <b><u>bold</u></b> {\\i1}italic{\\i0} 1 < 2 > 0\n\
+                    <font color=\"red\"></font>\n\
+                    <i>open <i>twice\n\n";
+        assert_eq!(
+            strip(text, Format::Srt, false, false),
+            "1\n00:00:01,000 --> 00:00:02,000\n\
+             Hello world\n\
+             bold italic 1 < 2 > 0\n\
+             open twice\n\n"
+        );
+        assert_eq!(
+            strip(text, Format::Srt, true, false),
+            "1\n00:00:01,000 --> 00:00:02,000\n\
+             <i>Hello</i> world\n\
+             bold <i>italic</i> 1 < 2 > 0\n\
+             <i>open twice</i>\n\n"
+        );
+        assert_eq!(
+            strip(text, Format::Srt, false, true),
+            "1\n00:00:01,000 --> 00:00:02,000\n\
+             {\\an8}Hello world\n\
+             bold italic 1 < 2 > 0\n\
+             open twice\n\n"
+        );
+    }
+
+    #[test]
+    fn test_strip_other_formats() {
+        let ass = "[Events]\n\
+                   Format: Layer, Start, End, Style, Text\n\
+                   Dialogue: 0,0:00:01.00,0:00:02.00,Default,{\\an8\\i1\\c&H0000FF&}Top{\\i0}{note} text\n";
+        assert!(strip(ass, Format::Ass, false, false).ends_with(",Default,Top text\n"));
+        assert!(
+            strip(ass, Format::Ass, true, true).ends_with(",Default,{\\an8\\i1}Top{\\i0} text\n")
+        );
+
+        let microdvd = "{25}{50}{y:i}{c:$0000ff}Hello|<b>world</b>\n\
+                        {75}{100}<i>Hi</i>|there\n";
+        assert_eq!(
+            strip(microdvd, Format::MicroDvd, true, false),
+            "{25}{50}{y:i}Hello|world\n{75}{100}{y:i}Hi|there\n"
+        );
+    }
+
+    #[test]
+    fn test_remove_empty() {
+        let text = "1\n00:00:01,000 --> 00:00:02,000\n<font color=\"red\"></font>\n\n\
+                    2\n00:00:03,000 --> 00:00:04,000\n<i>Two</i>\n\n";
+        assert_eq!(
+            strip(text, Format::Srt, false, false),
+            "2\n00:00:03,000 --> 00:00:04,000\nTwo\n\n"
+        );
+    }
+}
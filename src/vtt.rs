@@ -0,0 +1,339 @@
+use anyhow::{anyhow, bail, Result};
+use lazy_static::lazy_static;
+use regex::Regex;
+
+use crate::{parse_ms, Format, Milliseconds, SubData, Subtitle, TimeSpan};
+
+/// Parse the text of a WebVTT (.vtt) file. NOTE, STYLE and REGION blocks are kept verbatim
+/// and written back before the cue they preceded.
+pub(crate) fn parse(text: &str) -> Result<SubData> {
+    let mut input = text.split_inclusive('\n');
+    let first = input
+        .next()
+        .ok_or_else(|| anyhow!("The WebVTT file is empty"))?;
+    // "WEBVTT" may be followed by a space or a tab and more text, but nothing else:
+    let signature = first
+        .trim_end_matches(&['\r', '\n'][..])
+        .strip_prefix("WEBVTT");
+    if !signature.is_some_and(|rest| rest.is_empty() || rest.starts_with(&[' ', '\t'][..])) {
+        bail!(
+            "WebVTT files must start with \"WEBVTT\", found {:#?}",
+            first
+        );
+    }
+    let line_ending = if first.ends_with("\r\n") {
+        "\r\n"
+    } else {
+        "\n"
+    };
+    let with_ending = |line: &str| {
+        if line.ends_with('\n') {
+            line.to_string()
+        } else {
+            format!("{}{}", line, line_ending)
+        }
+    };
+
+    // The header is the "WEBVTT" line plus any lines up to the first blank line.
+    let mut header = vec![with_ending(first)];
+    // Each block is kept with the line number of its first line, for error messages.
+    let mut blocks: Vec<(usize, Vec<&str>)> = vec![];
+    let mut in_header = true;
+    for (i, line) in input.enumerate() {
+        if line.trim().is_empty() {
+            in_header = false;
+            if blocks.last().is_none_or(|(_, block)| !block.is_empty()) {
+                blocks.push((0, vec![]));
+            }
+        } else if in_header {
+            header.push(with_ending(line));
+        } else {
+            let (start, block) = blocks.last_mut().unwrap();
+            if block.is_empty() {
+                // The first line was already taken from the input:
+                *start = i + 2;
+            }
+            block.push(line);
+        }
+    }
+
+    let mut subs = Vec::new();
+    let mut preceding = Vec::new();
+    for (line_number, block) in blocks.into_iter().filter(|(_, block)| !block.is_empty()) {
+        if is_verbatim_block(block[0]) {
+            preceding.push(block.iter().map(|line| with_ending(line)).collect());
+            continue;
+        }
+
+        // A cue is an optional identifier, the timing line, then the payload.
+        let (identifier, timing_line) = if block[0].contains("-->") {
+            (None, 0)
+        } else {
+            (Some(block[0].trim_end().to_string()), 1)
+        };
+        let timing = block.get(timing_line).ok_or_else(|| {
+            anyhow!(
+                "line {}: Cue {:#?} has no timing line",
+                line_number,
+                block[0]
+            )
+        })?;
+        let line_number = line_number + timing_line;
+
+        // looking for 00:08.614 --> 00:00:10.373 line:0 position:50%
+        lazy_static! {
+            static ref RE: Regex = Regex::new(r"^\s*(\S+)\s+-->\s+(\S+)(?:\s+(.*?))?\s*$").unwrap();
+        }
+        let captures = RE.captures(timing).ok_or_else(|| {
+            anyhow!(
+                "line {}: Expecting time --> time; got: {:#?}",
+                line_number,
+                timing
+            )
+        })?;
+        let time = |group: usize| {
+            let found = captures.get(group).unwrap();
+            parse_ms(found.as_str()).map_err(|err| {
+                let column = timing[..found.start()].chars().count() + 1;
+                anyhow!("line {}, column {}: {}", line_number, column, err)
+            })
+        };
+        let start_ms = time(1)?;
+        let end_ms = time(2)?;
+        let settings = captures
+            .get(3)
+            .map(|m| m.as_str().to_string())
+            .filter(|s| !s.is_empty());
+
+        let number = identifier
+            .as_ref()
+            .and_then(|id| id.parse().ok())
+            .unwrap_or(subs.len() as i64 + 1);
+        subs.push(Subtitle {
+            number,
+            time_span: TimeSpan::new(start_ms, end_ms),
+            lines: block[timing_line + 1..]
+                .iter()
+                .map(|line| with_ending(line))
+                .collect(),
+            identifier,
+            settings,
+            preceding: std::mem::take(&mut preceding),
+            ..Default::default()
+        });
+    }
+
+    Ok(SubData {
+        subs,
+        line_ending: line_ending.to_string(),
+        format: Format::Vtt,
+        header,
+        trailer: preceding,
+        ..Default::default()
+    })
+}
+
+/// Is this the first line of a comment, style or region block?
+fn is_verbatim_block(first_line: &str) -> bool {
+    let first_line = first_line.trim_end();
+    first_line == "NOTE"
+        || first_line.starts_with("NOTE ")
+        || first_line.starts_with("NOTE\t")
+        || first_line == "STYLE"
+        || first_line == "REGION"
+}
+
+pub(crate) fn write(data: &SubData, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    if data.header.is_empty() {
+        write!(f, "WEBVTT{}", data.line_ending)?;
+    }
+    for line in data.header.iter() {
+        f.write_str(line)?;
+    }
+    f.write_str(&data.line_ending)?;
+
+    for sub in data.subs.iter() {
+        for block in sub.preceding.iter() {
+            write!(f, "{}{}", block, data.line_ending)?;
+        }
+        if let Some(ref id) = sub.identifier {
+            write!(f, "{}{}", id, data.line_ending)?;
+        }
+        write!(
+            f,
+            "{} --> {}",
+            vtt_timestamp(sub.time_span.start_ms),
+            vtt_timestamp(sub.time_span.end_ms)
+        )?;
+        if let Some(ref settings) = sub.settings {
+            write!(f, " {}", settings)?;
+        }
+        f.write_str(&data.line_ending)?;
+        for line in sub.lines.iter() {
+            f.write_str(line)?;
+        }
+        f.write_str(&data.line_ending)?;
+    }
+
+    for block in data.trailer.iter() {
+        write!(f, "{}{}", block, data.line_ending)?;
+    }
+    Ok(())
+}
+
+/// Format milliseconds as [-]hh:mm:ss.MMM. WebVTT uses a period where SubRip uses a comma.
+fn vtt_timestamp(ms: i64) -> String {
+    Milliseconds(ms).to_string().replace(',', ".")
+}
+
+/// Set (or with `None`, remove) the `line:` cue setting, keeping the other settings.
+pub fn set_line_setting(settings: &mut Option<String>, line: Option<&str>) {
+    let mut parts: Vec<String> = settings
+        .iter()
+        .flat_map(|s| s.split_whitespace())
+        .filter(|part| !part.starts_with("line:"))
+        .map(String::from)
+        .collect();
+    if let Some(line) = line {
+        parts.insert(0, format!("line:{}", line));
+    }
+    *settings = if parts.is_empty() {
+        None
+    } else {
+        Some(parts.join(" "))
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Format, SubData, TimeSpan};
+
+    use super::set_line_setting;
+
+    static SAMPLE: &str = "WEBVTT - sample\n\
+        Kind: captions\n\
+        \n\
+        STYLE\n\
+        ::cue { color: yellow }\n\
+        \n\
+        NOTE this is\n\
+        a comment\n\
+        \n\
+        intro\n\
+        00:01.000 --> 00:00:02.500 line:0 position:20%\n\
+        Hello\n\
+        \n\
+        2\n\
+        00:03.000 --> 00:04.000\n\
+        <i>World</i>\n\
+        again\n\
+        \n\
+        00:05.000 --> 00:06.000\n\
+        Bye\n\
+        \n\
+        NOTE the end\n";
+
+    #[test]
+    fn test_parse_vtt() {
+        let data = SubData::parse(SAMPLE, Format::Vtt, None).unwrap();
+        assert_eq!(data.header, vec!["WEBVTT - sample\n", "Kind: captions\n"]);
+        assert_eq!(data.subs.len(), 3);
+
+        let first = &data.subs[0];
+        assert_eq!(first.identifier.as_deref(), Some("intro"));
+        assert_eq!(first.time_span, TimeSpan::new(1000, 2500));
+        assert_eq!(first.settings.as_deref(), Some("line:0 position:20%"));
+        assert_eq!(
+            first.preceding,
+            vec![
+                "STYLE\n::cue { color: yellow }\n",
+                "NOTE this is\na comment\n"
+            ]
+        );
+        assert_eq!(first.lines, vec!["Hello\n"]);
+
+        assert_eq!(data.subs[1].number, 2);
+        assert_eq!(data.subs[1].lines, vec!["<i>World</i>\n", "again\n"]);
+        assert_eq!(data.subs[2].identifier, None);
+        assert_eq!(data.trailer, vec!["NOTE the end\n"]);
+
+        assert!(SubData::parse("1\n00:00:01,000 --> 00:00:02,000\n", Format::Vtt, None).is_err());
+        let error = SubData::parse(
+            "WEBVTT\n\nNOTE x\n\n\n1\n00:00:01.000 --> 00:00:0x.000\nHi\n",
+            Format::Vtt,
+            None,
+        )
+        .err()
+        .unwrap();
+        assert_eq!(
+            error.to_string(),
+            "line 7, column 18: Cannot coerce value into timestamp: 00:00:0x.000"
+        );
+        assert!(SubData::parse(
+            "WEBVTTX\n\n00:01.000 --> 00:02.000\nHi\n",
+            Format::Vtt,
+            None
+        )
+        .is_err());
+        assert!(SubData::parse(
+            "WEBVTT\tx\r\n\r\n00:01.000 --> 00:02.000\r\nHi\r\n",
+            Format::Vtt,
+            None
+        )
+        .is_ok());
+    }
+
+    #[test]
+    fn test_vtt_round_trip() {
+        let data = SubData::parse(SAMPLE, Format::Vtt, None).unwrap();
+        assert_eq!(
+            format!("{}", data),
+            "WEBVTT - sample\n\
+            Kind: captions\n\
+            \n\
+            STYLE\n\
+            ::cue { color: yellow }\n\
+            \n\
+            NOTE this is\n\
+            a comment\n\
+            \n\
+            intro\n\
+            00:00:01.000 --> 00:00:02.500 line:0 position:20%\n\
+            Hello\n\
+            \n\
+            2\n\
+            00:00:03.000 --> 00:00:04.000\n\
+            <i>World</i>\n\
+            again\n\
+            \n\
+            00:00:05.000 --> 00:00:06.000\n\
+            Bye\n\
+            \n\
+            NOTE the end\n\
+            \n"
+        );
+    }
+
+    #[test]
+    fn test_keep_identifiers() {
+        let text = "WEBVTT\n\n007\n00:00:01.000 --> 00:00:02.000\nHi\n\n";
+        let data = SubData::parse(text, Format::Vtt, None).unwrap();
+        assert_eq!(data.subs[0].number, 7);
+        assert_eq!(data.to_string(), text);
+    }
+
+    #[test]
+    fn test_set_line_setting() {
+        let mut settings = Some("position:20% line:80%".to_string());
+        set_line_setting(&mut settings, Some("0"));
+        assert_eq!(settings.as_deref(), Some("line:0 position:20%"));
+        set_line_setting(&mut settings, None);
+        assert_eq!(settings.as_deref(), Some("position:20%"));
+
+        let mut settings = None;
+        set_line_setting(&mut settings, Some("0"));
+        assert_eq!(settings.as_deref(), Some("line:0"));
+        set_line_setting(&mut settings, None);
+        assert_eq!(settings, None);
+    }
+}
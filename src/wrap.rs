@@ -0,0 +1,259 @@
+//! Reflowing the text of subtitles into balanced lines, for `--rewrap`.
+
+use unicode_width::UnicodeWidthChar;
+
+use crate::tags::TAG;
+use crate::Subtitle;
+
+/// Words that a line can start with, because they start a new clause.
+pub(crate) const CONJUNCTIONS: &[&str] = &[
+    "and", "but", "or", "nor", "so", "yet", "because", "although", "though", "unless", "until",
+    "while", "when", "where", "which", "who", "that", "if", "than",
+];
+
+/// Characters that end a clause, so a line can end after them.
+pub(crate) const CLAUSE_ENDS: &[char] = &[
+    ',', '.', ';', ':', '!', '?', '，', '。', '、', '；', '：', '！', '？',
+];
+
+/// Characters that can't start a line in Chinese and Japanese text.
+const NO_BREAK_BEFORE: &[char] = &[
+    '，', '。', '、', '；', '：', '！', '？', '）', '」', '』', '》', '〉', '…', 'ー',
+];
+
+/// A part of the text that can't be broken up, like a word. Tags count as part of it.
+pub(crate) struct Segment {
+    pub(crate) text: String,
+    pub(crate) width: usize,
+    pub(crate) space_before: bool,
+}
+
+/// Reflow the text of each subtitle into one line, or two balanced lines, of at most
+/// `max_width` columns. CJK characters are two columns wide. Lines are broken after
+/// punctuation or before a conjunction when possible. Dialogue with a dash for each speaker
+/// keeps a line for each. A warning is returned for each subtitle that doesn't fit.
+pub fn rewrap(subs: &mut [Subtitle], max_width: usize, line_ending: &str) -> Vec<String> {
+    let mut warnings = vec![];
+    for sub in subs {
+        let text: Vec<&str> = sub
+            .lines
+            .iter()
+            .map(|line| line.trim())
+            .filter(|line| !line.is_empty())
+            .collect();
+        let is_dialogue = text.len() > 1
+            && text
+                .iter()
+                .all(|line| TAG.replace_all(line, "").starts_with('-'));
+        let lines = if is_dialogue {
+            text.iter().map(|line| line.to_string()).collect()
+        } else {
+            wrap(&segments(&text.join(" ")), max_width)
+        };
+
+        if lines.len() > 2 || lines.iter().any(|line| width(line) > max_width) {
+            warnings.push(format!(
+                "Subtitle {} doesn't fit on two lines of {} characters.",
+                sub.number, max_width
+            ));
+        }
+        sub.lines = lines
+            .into_iter()
+            .map(|line| format!("{}{}", line, line_ending))
+            .collect();
+    }
+    warnings
+}
+
+/// The text as one line, or broken into two at the best place.
+fn wrap(segments: &[Segment], max_width: usize) -> Vec<String> {
+    let total = line_width(segments);
+    if total <= max_width || segments.len() < 2 {
+        return vec![join(segments)];
+    }
+
+    let cost = |k: usize| {
+        let (first, second) = (line_width(&segments[..k]), line_width(&segments[k..]));
+        let overflow = first.max(second).saturating_sub(max_width);
+        let ends_clause = TAG
+            .replace_all(&segments[k - 1].text, "")
+            .ends_with(CLAUSE_ENDS);
+        let next_word = TAG.replace_all(&segments[k].text, "").to_lowercase();
+        let penalty = if ends_clause {
+            0
+        } else if CONJUNCTIONS.contains(&next_word.as_str()) {
+            max_width / 4
+        } else {
+            max_width / 2
+        };
+        overflow * 1000 + first.abs_diff(second) + penalty
+    };
+    let best = (1..segments.len()).min_by_key(|&k| cost(k)).unwrap();
+    vec![join(&segments[..best]), join(&segments[best..])]
+}
+
+/// Split text into words, and CJK text into characters, keeping tags with them.
+pub(crate) fn segments(text: &str) -> Vec<Segment> {
+    let mut segments = vec![];
+    for (i, word) in text.split_whitespace().enumerate() {
+        let mut current = Segment {
+            text: String::new(),
+            width: 0,
+            space_before: i > 0,
+        };
+        let mut after_wide = false;
+        let mut rest = word;
+        while let Some(c) = rest.chars().next() {
+            if let Some(tag) = TAG.find(rest).filter(|tag| tag.start() == 0) {
+                current.text.push_str(tag.as_str());
+                rest = &rest[tag.end()..];
+                continue;
+            }
+            let width = c.width().unwrap_or(0);
+            let wide = width > 1;
+            if (wide || after_wide) && current.width > 0 && !NO_BREAK_BEFORE.contains(&c) {
+                segments.push(std::mem::replace(
+                    &mut current,
+                    Segment {
+                        text: String::new(),
+                        width: 0,
+                        space_before: false,
+                    },
+                ));
+            }
+            current.text.push(c);
+            current.width += width;
+            after_wide = wide;
+            rest = &rest[c.len_utf8()..];
+        }
+        segments.push(current);
+    }
+    segments
+}
+
+pub(crate) fn line_width(segments: &[Segment]) -> usize {
+    segments
+        .iter()
+        .enumerate()
+        .map(|(i, segment)| segment.width + (i > 0 && segment.space_before) as usize)
+        .sum()
+}
+
+fn join(segments: &[Segment]) -> String {
+    let mut line = String::new();
+    for (i, segment) in segments.iter().enumerate() {
+        if i > 0 && segment.space_before {
+            line.push(' ');
+        }
+        line.push_str(&segment.text);
+    }
+    line
+}
+
+/// How many columns a line takes up, without its tags.
+pub(crate) fn width(line: &str) -> usize {
+    TAG.replace_all(line, "")
+        .chars()
+        .map(|c| c.width().unwrap_or(0))
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Subtitle, TimeSpan};
+
+    use super::rewrap;
+
+    fn wrapped(lines: &[&str], max_width: usize) -> (Vec<String>, usize) {
+        let mut subs = vec![Subtitle {
+            number: 1,
+            time_span: TimeSpan::new(0, 1000),
+            lines: lines.iter().map(|line| format!("{}\n", line)).collect(),
+            ..Default::default()
+        }];
+        let warnings = rewrap(&mut subs, max_width, "\n");
+        let lines = subs[0]
+            .lines
+            .iter()
+            .map(|line| line.trim_end().to_string())
+            .collect();
+        (lines, warnings.len())
+    }
+
+    #[test]
+    fn test_rewrap() {
+        // Short lines are joined:
+        assert_eq!(
+            wrapped(&["I know", "you do."], 42),
+            (vec!["I know you do.".to_string()], 0)
+        );
+        // Balanced, breaking after punctuation:
+        assert_eq!(
+            wrapped(
+                &["I told you yesterday, we can't go to the party tonight anymore."],
+                42
+            ),
+            (
+                vec![
+                    "I told you yesterday,".to_string(),
+                    "we can't go to the party tonight anymore.".to_string()
+                ],
+                0
+            )
+        );
+        // Before a conjunction, keeping tags:
+        assert_eq!(
+            wrapped(
+                &[
+                    "{\\an8}<i>We looked everywhere for him",
+                    "but nobody had seen him at all</i>"
+                ],
+                42
+            ),
+            (
+                vec![
+                    "{\\an8}<i>We looked everywhere for him".to_string(),
+                    "but nobody had seen him at all</i>".to_string()
+                ],
+                0
+            )
+        );
+        // Without good places, the lines are balanced:
+        assert_eq!(
+            wrapped(
+                &["one two three four five six seven eight nine ten eleven"],
+                42
+            ),
+            (
+                vec![
+                    "one two three four five six".to_string(),
+                    "seven eight nine ten eleven".to_string()
+                ],
+                0
+            )
+        );
+        // Dialogue keeps a line for each speaker:
+        assert_eq!(
+            wrapped(&["- Are you coming?", "- Yes."], 42),
+            (
+                vec!["- Are you coming?".to_string(), "- Yes.".to_string()],
+                0
+            )
+        );
+        // CJK characters are two columns wide, and lines don't start with punctuation:
+        assert_eq!(
+            wrapped(
+                &["あちこち探したけど、誰もあなたがどこに行ったか知らなかった。"],
+                42
+            ),
+            (
+                vec![
+                    "あちこち探したけど、".to_string(),
+                    "誰もあなたがどこに行ったか知らなかった。".to_string()
+                ],
+                0
+            )
+        );
+        assert_eq!(wrapped(&[&"word ".repeat(30)], 42).1, 1);
+    }
+}